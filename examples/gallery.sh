@@ -24,20 +24,15 @@ fi
 out_dir="${1:-.}"
 mkdir -p "$out_dir"
 
-# Shared size and quality settings. The table palette resolves fine detail
-# better than rainbow at these zoom depths.
-common=(--width 1920 --height 1080 --palette table --sampler stratified --samples 16 --filter mitchell)
-
+# Each location is described by a scene file in examples/scenes, which are all
+# rendered by one process:
+#
 # 1. Mini-Mandelbrot: a complete miniature copy of the whole set, hidden among
 #    the filaments (the set is self-similar).
-"$mandelbrot" --center-re -0.1592 --center-im 1.0317 --scale 0.02 --max-iterations 1200 "${common[@]}" -o "$out_dir/minibrot.png"
-
 # 2. Seahorse Valley: interlocking spiral "seahorse tail" shapes in the neck
 #    between the main cardioid and the large left bulb.
-"$mandelbrot" --center-re -0.7453 --center-im 0.1127 --scale 0.0055 --max-iterations 1500 "${common[@]}" -o "$out_dir/seahorse.png"
-
 # 3. Elephant Valley: a parade of spiral "elephant" trunks along the right side
 #    of the cardioid.
-"$mandelbrot" --center-re 0.3 --center-im 0.02 --scale 0.02 --max-iterations 800 "${common[@]}" -o "$out_dir/elephant.png"
+"$mandelbrot" batch "$repo_root/examples/scenes" --output-dir "$out_dir"
 
 echo "Wrote $out_dir/minibrot.png, $out_dir/seahorse.png and $out_dir/elephant.png"
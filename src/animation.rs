@@ -0,0 +1,265 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Animations: sequences of frames, each rendered as a separate image with the usual pipeline.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crate::math::Spline;
+use crate::output::{AnimationWriter, Y4mWriter};
+use crate::palette::Edge;
+use crate::values::ValueFile;
+use crate::{Args, Sink, build_palette, build_transfer, colorize_values, options_text, render_to, value_file_values};
+
+/// The view of a single frame of an animation, and the palette offset to color it with.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub center_re: f64,
+    pub center_im: f64,
+    pub scale: f64,
+    pub rotation: f64,
+    pub max_iterations: u64,
+    pub palette_offset: f64,
+}
+
+/// A keyframe of a camera path: the view at a point in time, in seconds.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    time: f64,
+    center_re: f64,
+    center_im: f64,
+    scale: f64,
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default)]
+    palette_offset: f64,
+    max_iterations: Option<u64>,
+}
+
+/// A keyframe file: a list of `[[keyframes]]` tables.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeFile {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Frame {
+    /// Returns the view of the arguments.
+    pub fn from_args(args: &Args) -> Frame {
+        Frame {
+            center_re: args.center_re,
+            center_im: args.center_im,
+            scale: args.scale,
+            rotation: args.rotation,
+            max_iterations: args.max_iterations,
+            palette_offset: args.palette.palette_offset,
+        }
+    }
+}
+
+// ===== Zoom ==================================================================================================================================================
+
+/// Computes the frames of a zoom from `start` to `end`.
+///
+/// The scale is interpolated geometrically, so that every frame zooms in (or out) by the same factor.
+/// The center moves in proportion to the change in scale: quickly while the view is wide and slowly
+/// as it narrows, so that the end center stays in view while zooming in on it. If the scale does not
+/// change, the center moves with a smoothstep instead. The maximum number of iterations is
+/// interpolated linearly over the frames, which is linear in the zoom depth. The rotation and palette
+/// offset are those of the start.
+pub fn zoom(start: Frame, end: Frame, frame_count: u32) -> Vec<Frame> {
+    (0..frame_count)
+        .map(|index| {
+            let t = if frame_count > 1 { index as f64 / (frame_count - 1) as f64 } else { 0.0 };
+
+            let scale = start.scale * (end.scale / start.scale).powf(t);
+            let center_t = if start.scale != end.scale { (start.scale - scale) / (start.scale - end.scale) } else { t * t * (3.0 - 2.0 * t) };
+            let max_iterations = start.max_iterations as f64 + (end.max_iterations as f64 - start.max_iterations as f64) * t;
+
+            Frame {
+                center_re: start.center_re + (end.center_re - start.center_re) * center_t,
+                center_im: start.center_im + (end.center_im - start.center_im) * center_t,
+                scale,
+                rotation: start.rotation,
+                max_iterations: max_iterations.round() as u64,
+                palette_offset: start.palette_offset,
+            }
+        })
+        .collect()
+}
+
+// ===== Keyframes =============================================================================================================================================
+
+/// Reads a keyframe file, and checks that the keyframes make up a camera path.
+pub fn read_keyframes(path: &Path) -> Result<Vec<Keyframe>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read keyframe file {}: {e}", path.display()))?;
+    let file: KeyframeFile = toml::from_str(&text).map_err(|e| format!("cannot parse keyframe file {}: {e}", path.display()))?;
+
+    let keyframes = file.keyframes;
+    if keyframes.len() < 2 {
+        return Err(format!("keyframe file {} must contain at least two keyframes", path.display()).into());
+    }
+    for (index, keyframe) in keyframes.iter().enumerate() {
+        if !(keyframe.scale > 0.0 && keyframe.scale.is_finite()) {
+            return Err(format!("keyframe {} in {}: scale must be positive, got {}", index + 1, path.display(), keyframe.scale).into());
+        }
+        if !(keyframe.time.is_finite() && (index == 0 || keyframe.time > keyframes[index - 1].time)) {
+            return Err(format!("keyframe {} in {}: times must be increasing, got {}", index + 1, path.display(), keyframe.time).into());
+        }
+    }
+
+    Ok(keyframes)
+}
+
+/// Returns the duration of a camera path, in seconds.
+pub fn duration(keyframes: &[Keyframe]) -> f64 {
+    keyframes[keyframes.len() - 1].time - keyframes[0].time
+}
+
+/// Computes `frame_count` frames along a camera path, evenly spaced in time from the first keyframe
+/// to the last. Keyframes without a maximum number of iterations use `max_iterations`.
+///
+/// Each property is interpolated with a cubic Hermite spline through the keyframes, with Catmull-Rom
+/// tangents, so that the camera passes through every keyframe without sudden changes in speed. The
+/// scale is interpolated in log space, which makes zooming by a constant factor per second a straight
+/// line. The rotation is in degrees and is not wrapped: going from 0 to 360 makes a full turn.
+pub fn camera_path(keyframes: &[Keyframe], max_iterations: u64, frame_count: u32) -> Vec<Frame> {
+    let times: Vec<f64> = keyframes.iter().map(|keyframe| keyframe.time).collect();
+    let spline = |f: &dyn Fn(&Keyframe) -> f64| Spline::catmull_rom(times.clone(), keyframes.iter().map(f).collect());
+
+    let center_re = spline(&|keyframe| keyframe.center_re);
+    let center_im = spline(&|keyframe| keyframe.center_im);
+    let log_scale = spline(&|keyframe| keyframe.scale.ln());
+    let rotation = spline(&|keyframe| keyframe.rotation);
+    let palette_offset = spline(&|keyframe| keyframe.palette_offset);
+    let iterations = spline(&|keyframe| keyframe.max_iterations.unwrap_or(max_iterations) as f64);
+
+    (0..frame_count)
+        .map(|index| {
+            let t = if frame_count > 1 { index as f64 / (frame_count - 1) as f64 } else { 0.0 };
+            let time = times[0] + (times[times.len() - 1] - times[0]) * t;
+
+            Frame {
+                center_re: center_re.evaluate(time),
+                center_im: center_im.evaluate(time),
+                scale: log_scale.evaluate(time).exp(),
+                rotation: rotation.evaluate(time),
+                max_iterations: iterations.evaluate(time).round().max(1.0) as u64,
+                palette_offset: palette_offset.evaluate(time),
+            }
+        })
+        .collect()
+}
+
+// ===== Rendering =============================================================================================================================================
+
+/// Renders the frames of an animation one after another, with the options in `args` for everything
+/// but the view.
+///
+/// If `video` is given, the frames are streamed to it as Y4M (see [`Y4mWriter`]). Otherwise frame `n`
+/// is written to the output path with the frame number appended to its file name, for example
+/// `zoom-0042.png` for output `zoom.png`.
+pub fn render_frames(args: &Args, frames: &[Frame], video: Option<&Path>, frame_rate: f64) -> Result<(), Box<dyn Error>> {
+    if args.export_values.is_some() || args.cache.is_some() {
+        return Err("--export-values and --cache cannot be used for animations".into());
+    }
+
+    let video_stream = video.map(|path| Y4mWriter::create(path, args.width, args.height, frame_rate)).transpose()?.map(RefCell::new);
+
+    let start_time = Instant::now();
+    let digits = (frames.len().saturating_sub(1).to_string().len()).max(4);
+    for (index, frame) in frames.iter().enumerate() {
+        let path = frame_path(&args.output.output, index, digits);
+        match video {
+            Some(video) => eprintln!("Frame {}/{}: {}", index + 1, frames.len(), video.display()),
+            None => eprintln!("Frame {}/{}: {}", index + 1, frames.len(), path.display()),
+        }
+
+        let mut frame_args = args.clone();
+        frame_args.center_re = frame.center_re;
+        frame_args.center_im = frame.center_im;
+        frame_args.scale = frame.scale;
+        frame_args.rotation = frame.rotation;
+        frame_args.max_iterations = frame.max_iterations;
+        frame_args.palette.palette_offset = frame.palette_offset;
+        frame_args.output.output = path;
+        render_to(&frame_args, video_stream.as_ref().map(Sink::Video))?;
+    }
+    if let Some(video_stream) = video_stream {
+        video_stream.into_inner().finish()?;
+    }
+    eprintln!("Rendered {} frames in {} ms", frames.len(), start_time.elapsed().as_millis());
+
+    Ok(())
+}
+
+/// Returns the path of a frame: the output path with the zero-padded frame number appended to the
+/// file name.
+fn frame_path(output: &Path, index: usize, digits: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match output.extension() {
+        Some(extension) => format!("{stem}-{index:0digits$}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{index:0digits$}"),
+    };
+    output.with_file_name(file_name)
+}
+
+// ===== Palette cycling =======================================================================================================================================
+
+/// Renders the image of `args` once, and writes an animation that cycles its colors through the
+/// palette: over `frame_count` frames, the palette offset goes once from its value in `args` to that
+/// value plus 1. The animation is written to the output path as an animated PNG or GIF.
+pub fn cycle_palette(args: &Args, frame_count: u32, frame_rate: f64) -> Result<(), Box<dyn Error>> {
+    if frame_count == 0 {
+        return Err("--frames must be positive".into());
+    }
+    if !(frame_rate > 0.0 && frame_rate.is_finite()) {
+        return Err(format!("--frame-rate must be positive, got {frame_rate}").into());
+    }
+    if args.export_values.is_some() {
+        return Err("--export-values cannot be used for animations; use --cache to keep the values".into());
+    }
+    if args.output.alpha {
+        return Err("--alpha cannot be used for animated PNG and GIF output".into());
+    }
+    let (format, bit_depth) = args.output.resolve()?;
+
+    let values = RefCell::new(None);
+    render_to(args, Some(Sink::Values(&values)))?;
+    let values: ValueFile = values.into_inner().expect("rendered values");
+    let transfer = build_transfer(&args.palette, values.view().max_iterations)?;
+    let transferred = transfer.apply_all(&value_file_values(&values));
+
+    let start_time = Instant::now();
+    let mut writer = AnimationWriter::create(&args.output.output, format, bit_depth, (args.width, args.height), frame_count, frame_rate, &options_text(args)?)?;
+    for index in 0..frame_count {
+        let mut palette_args = args.palette.clone();
+        // Every frame goes through the same mapping, including the first one without an offset, so
+        // that the loop does not jump when it starts over.
+        palette_args.palette_edge.get_or_insert(Edge::Wrap);
+        palette_args.palette_offset = args.palette.palette_offset + index as f64 / frame_count as f64;
+        let palette = build_palette(&palette_args, values.view().max_iterations)?;
+        writer.write_frame(&colorize_values(&transferred, values.width(), values.height(), palette.as_ref()))?;
+    }
+    writer.finish()?;
+    eprintln!("Colored {} frames in {} ms", frame_count, start_time.elapsed().as_millis());
+
+    Ok(())
+}
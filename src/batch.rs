@@ -0,0 +1,159 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of several scenes one after another, in one process.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::{CommandFactory, FromArgMatches};
+
+use crate::{Cli, render, scene_to_args};
+
+/// A scene of a batch, with a description to report it by, the directory that relative paths in it
+/// are resolved against, and the output path to use if it does not have one.
+struct BatchScene {
+    description: String,
+    options: Result<toml::Table, String>,
+    base_dir: PathBuf,
+    default_output: PathBuf,
+}
+
+/// The list of scenes in a batch file.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchFile {
+    scenes: Vec<toml::Table>,
+}
+
+// ===== Batch rendering =======================================================================================================================================
+
+/// Renders the scenes in `path`, which is either a TOML file with a `[[scenes]]` list or a directory
+/// of scene files. A scene that fails does not stop the batch; the failures are listed in the summary
+/// at the end. If `output_dir` is given, relative output paths are resolved against it. A scene
+/// without an output path is written to a PNG image named after its scene file, and the directory
+/// of each output is created if it does not exist yet.
+pub fn render_batch(path: &Path, output_dir: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let scenes = if path.is_dir() { read_scene_directory(path)? } else { read_scene_list(path)? };
+
+    // The scenes start from the default options, as if each were rendered with only --scene.
+    let matches = Cli::command().try_get_matches_from(["mandelbrot"])?;
+    let defaults = Cli::from_arg_matches(&matches)?.args;
+
+    let start_time = Instant::now();
+    let mut failures = Vec::new();
+    let mut outputs = HashSet::new();
+    for (index, scene) in scenes.iter().enumerate() {
+        eprintln!("Scene {}/{}: {}", index + 1, scenes.len(), scene.description);
+        let scene_start_time = Instant::now();
+
+        let args = scene.options.clone().map_err(Into::into).and_then(|mut options| {
+            options.entry("output").or_insert_with(|| scene.default_output.to_string_lossy().into_owned().into());
+            scene_to_args(options, &scene.base_dir, &defaults, &matches)
+        });
+        let result = args.and_then(|mut args| {
+            if let Some(output_dir) = output_dir {
+                args.output.output = output_dir.join(&args.output.output);
+            }
+            if !outputs.insert(args.output.output.clone()) {
+                return Err(format!("output {} is also written by an earlier scene", args.output.output.display()).into());
+            }
+            if let Some(parent) = args.output.output.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent).map_err(|e| format!("cannot create directory {}: {e}", parent.display()))?;
+            }
+            render(&args)
+        });
+
+        match result {
+            Ok(()) => eprintln!("Scene {}/{} done in {} ms", index + 1, scenes.len(), scene_start_time.elapsed().as_millis()),
+            Err(e) => {
+                eprintln!("Scene {}/{} failed after {} ms: {e}", index + 1, scenes.len(), scene_start_time.elapsed().as_millis());
+                failures.push((scene, e));
+            }
+        }
+    }
+
+    eprintln!("Rendered {} of {} scenes in {} ms", scenes.len() - failures.len(), scenes.len(), start_time.elapsed().as_millis());
+    if failures.is_empty() {
+        return Ok(());
+    }
+    for (scene, e) in &failures {
+        eprintln!("Failed: {}: {e}", scene.description);
+    }
+    Err(format!("{} of {} scenes failed", failures.len(), scenes.len()).into())
+}
+
+/// Reads the `.toml` files in a directory, in the order of their names.
+fn read_scene_directory(path: &Path) -> Result<Vec<BatchScene>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(path).map_err(|e| format!("cannot read scene directory {}: {e}", path.display()))? {
+        let entry_path = entry?.path();
+        if entry_path.is_file() && entry_path.extension().is_some_and(|extension| extension == "toml") {
+            paths.push(entry_path);
+        }
+    }
+    paths.sort();
+
+    if paths.is_empty() {
+        return Err(format!("scene directory {} does not contain any .toml files", path.display()).into());
+    }
+
+    // A scene file that cannot be parsed is reported as a failure of that scene, when its turn comes.
+    Ok(paths
+        .into_iter()
+        .map(|scene_path| BatchScene {
+            description: scene_path.display().to_string(),
+            default_output: output_name(&scene_path, None),
+            options: std::fs::read_to_string(&scene_path)
+                .map_err(|e| format!("cannot read scene file: {e}"))
+                .and_then(|text| toml::from_str(&text).map_err(|e| format!("cannot parse scene file: {e}"))),
+            base_dir: path.to_path_buf(),
+        })
+        .collect())
+}
+
+/// Reads a batch file with a `[[scenes]]` list. Relative paths in the scenes are resolved against
+/// the directory of the batch file.
+fn read_scene_list(path: &Path) -> Result<Vec<BatchScene>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read batch file {}: {e}", path.display()))?;
+    let batch: BatchFile = toml::from_str(&text).map_err(|e| format!("cannot parse batch file {}: {e}", path.display()))?;
+    if batch.scenes.is_empty() {
+        return Err(format!("batch file {} does not contain any scenes", path.display()).into());
+    }
+
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    Ok(batch
+        .scenes
+        .into_iter()
+        .enumerate()
+        .map(|(index, options)| BatchScene {
+            description: format!("scene {} of {}", index + 1, path.display()),
+            default_output: output_name(path, Some(index)),
+            options: Ok(options),
+            base_dir: base_dir.to_path_buf(),
+        })
+        .collect())
+}
+
+/// Returns the default output path of a scene: a PNG image named after the file it comes from, with
+/// the number of the scene appended for a scene in a batch file.
+fn output_name(path: &Path, index: Option<usize>) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match index {
+        Some(index) => PathBuf::from(format!("{stem}-{}.png", index + 1)),
+        None => PathBuf::from(format!("{stem}.png")),
+    }
+}
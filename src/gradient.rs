@@ -0,0 +1,325 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Palettes in the formats of other programs: Fractint color maps (`.map`) and GIMP gradients
+//! (`.ggr`). Both are converted to color stops over `0.0..=1.0` for the table palette, which
+//! interpolates linearly in sRGB between them. The stops are colors with an opacity, which is
+//! always opaque for color maps.
+
+use std::f32::consts::PI;
+
+use image::Rgba;
+
+use crate::math::{hsv_to_srgb, srgb_to_hsv};
+
+/// Distance between the two stops of a sudden change of color, which the table palette cannot
+/// represent with a single stop.
+const HARD_EDGE: f64 = 1e-6;
+
+/// Number of stops that a GIMP gradient segment is sampled with when its blending cannot be
+/// represented exactly by linear interpolation.
+const SEGMENT_SAMPLES: usize = 16;
+
+/// Segments narrower than this are treated as a sudden change of color, as GIMP does.
+const EPSILON: f64 = 1e-10;
+
+// ===== Fractint maps =========================================================================================================================================
+
+/// Reads a Fractint color map: one color per line as three decimal components from 0 to 255,
+/// optionally followed by a comment. The colors are spread evenly over `0.0..=1.0`.
+pub fn read_fractint_map(text: &str) -> Result<Vec<(f64, Rgba<u8>)>, String> {
+    let mut colors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let Some(first) = fields.next() else { continue };
+        let component = |field: Option<&str>| {
+            field.and_then(|field| field.parse::<u8>().ok()).ok_or_else(|| format!("line {}: expected three color components from 0 to 255, got '{}'", index + 1, line.trim()))
+        };
+        colors.push(Rgba([component(Some(first))?, component(fields.next())?, component(fields.next())?, 255]));
+    }
+    if colors.len() < 2 {
+        return Err(format!("a color map must have at least two colors, got {}", colors.len()));
+    }
+
+    let last = (colors.len() - 1) as f64;
+    Ok(colors.into_iter().enumerate().map(|(index, color)| (index as f64 / last, color)).collect())
+}
+
+// ===== GIMP gradients ========================================================================================================================================
+
+/// A segment of a GIMP gradient: the colors at its ends, blended over `left..=right` with the
+/// midpoint (where the blend is halfway) at `middle`.
+struct Segment {
+    left: f64,
+    middle: f64,
+    right: f64,
+    left_color: [f32; 3],
+    right_color: [f32; 3],
+    left_alpha: f32,
+    right_alpha: f32,
+    blending: Blending,
+    coloring: Coloring,
+}
+
+/// How the position within a segment translates into the fraction of the way from the left color
+/// to the right color.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Blending {
+    Linear,
+    Curved,
+    Sine,
+    SphereIncreasing,
+    SphereDecreasing,
+    Step,
+}
+
+/// How the colors of a segment are interpolated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Coloring {
+    Rgb,
+    HsvCounterclockwise,
+    HsvClockwise,
+}
+
+/// Reads a GIMP gradient. Each segment is converted to stops: exactly for linear blending in RGB,
+/// and otherwise by sampling the segment. The opacity is blended like the colors.
+pub fn read_gimp_gradient(text: &str) -> Result<Vec<(f64, Rgba<u8>)>, String> {
+    let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+    match lines.next() {
+        Some((_, line)) if line.trim() == "GIMP Gradient" => {}
+        _ => return Err("line 1: expected 'GIMP Gradient'".to_string()),
+    }
+    let (index, mut line) = lines.next().ok_or("missing number of segments")?;
+    if line.starts_with("Name:") {
+        line = lines.next().ok_or("missing number of segments")?.1;
+    }
+    let count: usize = line.trim().parse().map_err(|_| format!("line {}: expected the number of segments, got '{}'", index + 1, line.trim()))?;
+
+    let segments = lines.take(count).map(|(index, line)| parse_segment(line).map_err(|e| format!("line {}: {e}", index + 1))).collect::<Result<Vec<_>, _>>()?;
+    if segments.len() != count || count == 0 {
+        return Err(format!("expected {count} segments, got {}", segments.len()));
+    }
+
+    let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let mut stops: Vec<(f64, Rgba<u8>)> = Vec::new();
+    for segment in &segments {
+        for (position, [r, g, b], alpha) in segment.stops() {
+            let color = Rgba([to_u8(r), to_u8(g), to_u8(b), to_u8(alpha)]);
+            match stops.last() {
+                Some(&(last, last_color)) if position <= last => {
+                    if color != last_color {
+                        stops.push((last + HARD_EDGE, color));
+                    }
+                }
+                _ => stops.push((position, color)),
+            }
+        }
+    }
+
+    // Hard edges at the end of the gradient went past 1; step back from 1 instead, and push back
+    // the stops before them as far as needed to keep the positions increasing.
+    for (steps, stop) in stops.iter_mut().rev().enumerate() {
+        let limit = 1.0 - steps as f64 * HARD_EDGE;
+        if stop.0 <= limit && steps > 0 {
+            break;
+        }
+        stop.0 = stop.0.min(limit);
+    }
+    Ok(stops)
+}
+
+/// Parses a segment line: left, middle and right positions, the left and right colors as RGBA, the
+/// blending and coloring types, and optionally the types of the end colors, which are ignored.
+fn parse_segment(line: &str) -> Result<Segment, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 13 && fields.len() != 15 {
+        return Err(format!("expected 13 or 15 fields in a segment, got {}", fields.len()));
+    }
+    let number = |index: usize| fields[index].parse::<f64>().map_err(|_| format!("invalid number '{}'", fields[index]));
+
+    let blending = match fields[11] {
+        "0" => Blending::Linear,
+        "1" => Blending::Curved,
+        "2" => Blending::Sine,
+        "3" => Blending::SphereIncreasing,
+        "4" => Blending::SphereDecreasing,
+        "5" => Blending::Step,
+        other => return Err(format!("unknown blending type '{other}'")),
+    };
+    let coloring = match fields[12] {
+        "0" => Coloring::Rgb,
+        "1" => Coloring::HsvCounterclockwise,
+        "2" => Coloring::HsvClockwise,
+        other => return Err(format!("unknown coloring type '{other}'")),
+    };
+
+    let segment = Segment {
+        left: number(0)?,
+        middle: number(1)?,
+        right: number(2)?,
+        left_color: [number(3)? as f32, number(4)? as f32, number(5)? as f32],
+        right_color: [number(7)? as f32, number(8)? as f32, number(9)? as f32],
+        left_alpha: number(6)? as f32,
+        right_alpha: number(10)? as f32,
+        blending,
+        coloring,
+    };
+    if !(0.0 <= segment.left && segment.left <= segment.middle && segment.middle <= segment.right && segment.right <= 1.0) {
+        return Err(format!("segment positions must satisfy 0 <= left <= middle <= right <= 1, got {} {} {}", segment.left, segment.middle, segment.right));
+    }
+    Ok(segment)
+}
+
+impl Segment {
+    /// Returns the stops that represent this segment, by increasing position, as the position, the
+    /// color and the opacity.
+    fn stops(&self) -> Vec<(f64, [f32; 3], f32)> {
+        let left = (self.left, self.left_color, self.left_alpha);
+        let right = (self.right, self.right_color, self.right_alpha);
+        let width = self.right - self.left;
+        if width < EPSILON {
+            return vec![left, right];
+        }
+
+        match (self.blending, self.coloring) {
+            // Linear blending in RGB is linear on either side of the midpoint, and stepping only ever
+            // takes the colors at the ends.
+            (Blending::Linear, Coloring::Rgb) => vec![left, (self.middle, self.color(0.5), self.alpha(0.5)), right],
+            (Blending::Step, _) => vec![left, (self.middle, self.left_color, self.left_alpha), (self.middle, self.right_color, self.right_alpha), right],
+            _ => (0..=SEGMENT_SAMPLES)
+                .map(|index| {
+                    let position = self.left + width * index as f64 / SEGMENT_SAMPLES as f64;
+                    let factor = self.factor(position);
+                    (position, self.color(factor), self.alpha(factor))
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the opacity a fraction `factor` of the way from the left color to the right color.
+    fn alpha(&self, factor: f32) -> f32 {
+        self.left_alpha + (self.right_alpha - self.left_alpha) * factor
+    }
+
+    /// Returns the fraction of the way from the left color to the right color at `position`, as GIMP
+    /// computes it.
+    fn factor(&self, position: f64) -> f32 {
+        let width = self.right - self.left;
+        let position = ((position - self.left) / width) as f32;
+        let middle = ((self.middle - self.left) / width) as f32;
+
+        let linear = if position <= middle {
+            if middle < EPSILON as f32 { 0.0 } else { 0.5 * position / middle }
+        } else if 1.0 - middle < EPSILON as f32 {
+            1.0
+        } else {
+            0.5 + 0.5 * (position - middle) / (1.0 - middle)
+        };
+
+        match self.blending {
+            Blending::Linear => linear,
+            Blending::Curved => position.powf(0.5f32.ln() / middle.max(EPSILON as f32).ln()),
+            Blending::Sine => ((-PI / 2.0 + PI * linear).sin() + 1.0) / 2.0,
+            Blending::SphereIncreasing => (1.0 - (linear - 1.0) * (linear - 1.0)).sqrt(),
+            Blending::SphereDecreasing => 1.0 - (1.0 - linear * linear).max(0.0).sqrt(),
+            Blending::Step => if position >= middle { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// Returns the color a fraction `factor` of the way from the left color to the right color.
+    fn color(&self, factor: f32) -> [f32; 3] {
+        let lerp = |left: f32, right: f32| left + (right - left) * factor;
+        match self.coloring {
+            Coloring::Rgb => [0, 1, 2].map(|i| lerp(self.left_color[i], self.right_color[i])),
+            Coloring::HsvCounterclockwise | Coloring::HsvClockwise => {
+                let [left_hue, left_saturation, left_value] = srgb_to_hsv(self.left_color);
+                let [right_hue, right_saturation, right_value] = srgb_to_hsv(self.right_color);
+                // The hue always goes around in the given direction, the long way if need be; like
+                // GIMP, a full turn if both hues are the same.
+                let turn = match self.coloring {
+                    Coloring::HsvCounterclockwise if left_hue < right_hue => right_hue - left_hue,
+                    Coloring::HsvCounterclockwise => 1.0 - (left_hue - right_hue),
+                    _ if right_hue < left_hue => right_hue - left_hue,
+                    _ => -(1.0 - (right_hue - left_hue)),
+                };
+                let hue = (left_hue + turn * factor).rem_euclid(1.0);
+                hsv_to_srgb([hue, lerp(left_saturation, right_saturation), lerp(left_value, right_value)])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractint_maps_spread_their_colors_evenly() {
+        let stops = read_fractint_map("0 0 0 black\n\n128 64 32\n255 255 255   white\n").unwrap();
+        assert_eq!(stops, vec![(0.0, Rgba([0, 0, 0, 255])), (0.5, Rgba([128, 64, 32, 255])), (1.0, Rgba([255, 255, 255, 255]))]);
+    }
+
+    #[test]
+    fn fractint_map_errors_point_at_the_line() {
+        assert_eq!(read_fractint_map("0 0 0\n1 2\n").unwrap_err(), "line 2: expected three color components from 0 to 255, got '1 2'");
+        assert_eq!(read_fractint_map("0 0 0\n256 0 0\n").unwrap_err(), "line 2: expected three color components from 0 to 255, got '256 0 0'");
+        assert!(read_fractint_map("0 0 0\n").unwrap_err().contains("at least two colors"));
+    }
+
+    #[test]
+    fn linear_gimp_segments_are_converted_exactly() {
+        let text = "GIMP Gradient\nName: Test\n1\n0.0 0.25 1.0 0 0 0 1 1 1 1 0.5 0 0\n";
+        let stops = read_gimp_gradient(text).unwrap();
+        assert_eq!(stops, vec![(0.0, Rgba([0, 0, 0, 255])), (0.25, Rgba([128, 128, 128, 191])), (1.0, Rgba([255, 255, 255, 128]))]);
+    }
+
+    #[test]
+    fn gimp_hard_edges_stay_within_the_palette() {
+        // A step segment, and a zero-width segment at 1.0 with two sudden changes of color.
+        let text = "GIMP Gradient\n2\n0.0 0.5 1.0 1 0 0 1 0 1 0 1 5 0\n1.0 1.0 1.0 0 0 1 1 1 1 1 1 0 0\n";
+        let stops = read_gimp_gradient(text).unwrap();
+        let positions: Vec<f64> = stops.iter().map(|&(position, _)| position).collect();
+        assert_eq!(positions, vec![0.0, 0.5, 0.5 + HARD_EDGE, 1.0 - 2.0 * HARD_EDGE, 1.0 - HARD_EDGE, 1.0]);
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{positions:?}");
+        assert_eq!(stops.iter().map(|&(_, color)| color).collect::<Vec<_>>()[3..], [Rgba([0, 255, 0, 255]), Rgba([0, 0, 255, 255]), Rgba([255, 255, 255, 255])]);
+    }
+
+    #[test]
+    fn gimp_hard_edges_push_back_the_stops_just_before_the_end() {
+        // A segment boundary just before 1.0, closer to it than the hard edges at the end need.
+        let text = "GIMP Gradient\n3\n0.0 0.5 0.9999985 1 0 0 1 0 1 0 1 0 0\n0.9999985 0.99999925 1.0 0 1 0 1 0 0 1 1 0 0\n1.0 1.0 1.0 1 1 1 1 0 0 0 1 0 0\n";
+        let stops = read_gimp_gradient(text).unwrap();
+        let positions: Vec<f64> = stops.iter().map(|&(position, _)| position).collect();
+        assert_eq!(positions, vec![0.0, 0.5, 1.0 - 4.0 * HARD_EDGE, 1.0 - 3.0 * HARD_EDGE, 1.0 - 2.0 * HARD_EDGE, 1.0 - HARD_EDGE, 1.0]);
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{positions:?}");
+    }
+
+    #[test]
+    fn gimp_hsv_segments_go_around_the_hue_in_the_given_direction() {
+        // From red to blue: counterclockwise goes through green, clockwise through magenta.
+        let counterclockwise = read_gimp_gradient("GIMP Gradient\n1\n0 0.5 1 1 0 0 1 0 0 1 1 0 1\n").unwrap();
+        let clockwise = read_gimp_gradient("GIMP Gradient\n1\n0 0.5 1 1 0 0 1 0 0 1 1 0 2\n").unwrap();
+        let middle = SEGMENT_SAMPLES / 2;
+        assert_eq!(counterclockwise[middle], (0.5, Rgba([0, 255, 0, 255])));
+        assert_eq!(clockwise[middle], (0.5, Rgba([255, 0, 255, 255])));
+    }
+
+    #[test]
+    fn gimp_gradient_errors_point_at_the_line() {
+        assert_eq!(read_gimp_gradient("GIMP Palette\n").unwrap_err(), "line 1: expected 'GIMP Gradient'");
+        assert_eq!(read_gimp_gradient("GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 9 0\n").unwrap_err(), "line 3: unknown blending type '9'");
+        assert!(read_gimp_gradient("GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n").unwrap_err().contains("expected 2 segments"));
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The library of built-in palettes for the table palette, selected with `--palette-name`. They are
+//! palette files in the `palettes` directory, embedded in the binary.
+
+use std::error::Error;
+
+use crate::{PaletteFile, parse_palette_text};
+
+/// A built-in palette: its name, a short description, and the text of its palette file.
+pub struct BuiltinPalette {
+    pub name: &'static str,
+    pub description: &'static str,
+    text: &'static str,
+}
+
+/// The built-in palettes, in the order in which they are listed.
+pub const PALETTES: [BuiltinPalette; 6] = [
+    BuiltinPalette {
+        name: "ultra-fractal",
+        description: "The default gradient of Ultra Fractal: blues, white and orange, cyclic",
+        text: include_str!("../palettes/ultra-fractal.toml"),
+    },
+    BuiltinPalette { name: "fire", description: "Black through red, orange and yellow to white", text: include_str!("../palettes/fire.toml") },
+    BuiltinPalette { name: "ice", description: "Black through deep blue and azure to white", text: include_str!("../palettes/ice.toml") },
+    BuiltinPalette { name: "viridis", description: "Matplotlib's viridis: dark purple through blue and green to yellow", text: include_str!("../palettes/viridis.toml") },
+    BuiltinPalette { name: "magma", description: "Matplotlib's magma: black through purple and red to pale yellow", text: include_str!("../palettes/magma.toml") },
+    BuiltinPalette { name: "twilight", description: "Matplotlib's twilight: pale gray, blue, dark purple and red, cyclic", text: include_str!("../palettes/twilight.toml") },
+];
+
+/// Returns the built-in palette with the given name.
+pub fn builtin_palette(name: &str) -> Result<PaletteFile, Box<dyn Error>> {
+    let palette = PALETTES.iter().find(|palette| palette.name == name).ok_or_else(|| {
+        let names: Vec<&str> = PALETTES.iter().map(|palette| palette.name).collect();
+        format!("unknown palette name '{name}', expected one of: {}", names.join(", "))
+    })?;
+    Ok(parse_palette_text(palette.text, &format!("built-in palette {name}"))?)
+}
+
+/// Prints the names and descriptions of the built-in palettes.
+pub fn list_palettes() {
+    for palette in &PALETTES {
+        println!("{:<16}{}", palette.name, palette.description);
+    }
+}
@@ -14,34 +14,89 @@
 
 #![allow(dead_code)]
 
+use std::cell::RefCell;
 use std::error::Error;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-use clap::{Parser, ValueEnum};
-use image::{Rgb, RgbImage};
+use clap::builder::ArgPredicate;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use image::{Rgb, Rgb32FImage, Rgba, Rgba32FImage};
 use num_complex::Complex64;
 use rayon::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::palette::{Entry, Grayscale, Palette, Rainbow, TablePalette};
+use crate::animation::Frame;
+use crate::output::{BitDepth, Format, PngRowWriter, TextChunk, Y4mWriter, read_png_text, save_image, save_rgba_image};
+use crate::palette::{ColorSpace, Edge, Entry, Grayscale, Interpolation, Mapping, Palette, Rainbow, TablePalette};
 use crate::reconstruction::{Reconstructor, RendererOutput};
-use crate::reconstruction::filter::{BoxFilter, Filter, MitchellFilter};
+use crate::reconstruction::filter::{BoxFilter, CatmullRomFilter, Filter, GaussianFilter, LanczosFilter, MitchellFilter, TableFilter, TriangleFilter};
 use crate::rendering::mandelbrot::MandelbrotRenderer;
 use crate::rendering::Renderer;
 use crate::sampling::Sampler;
+use crate::sampling::multi_jittered::MultiJitteredSampler;
 use crate::sampling::simple::SimpleSampler;
 use crate::sampling::stratified::StratifiedSampler;
+use crate::transfer::{Transfer, TransferFunction};
+use crate::values::{ValueFile, View};
 
+mod animation;
+mod batch;
+mod color_names;
+mod gradient;
+mod library;
 mod palette;
 mod math;
+mod output;
+mod preview;
 mod sampling;
 mod reconstruction;
 mod rendering;
+mod transfer;
+mod values;
 
 /// Mandelbrot fractal generator using sampling and reconstruction.
 #[derive(Parser)]
-#[command(version, about)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Color a value file written with --export-values or --cache, without rendering again.
+    Recolor(RecolorArgs),
+
+    /// Render a list of scenes, or a directory of scene files, one after another.
+    Batch(BatchArgs),
+
+    /// Render the frames of a zoom from the view given by the render options to an end view, or of a
+    /// camera path through keyframes.
+    Animate(Box<AnimateArgs>),
+
+    /// Render an image once, and cycle its colors through the palette in an animated PNG or GIF.
+    Cycle(Box<CycleArgs>),
+
+    /// Draw the palette as a strip with its stops and the distribution of the values of an image, or
+    /// export it as a palette file.
+    Palette(Box<PaletteCommandArgs>),
+
+    /// List the built-in palettes that can be selected with --palette-name.
+    Palettes,
+}
+
+/// Options for rendering an image, which is what happens when no subcommand is given.
+///
+/// The options are also serializable, so that they can be embedded in the output image. The
+/// serialized keys are the same as the argument ids, which is what allows values read back from an
+/// image to be merged with the options given on the command line (see [`args_from_image`]).
+#[derive(Clone, clap::Args, Serialize, Deserialize)]
 struct Args {
     /// Image width in pixels.
     #[arg(long, default_value_t = 1920)]
@@ -51,6 +106,10 @@ struct Args {
     #[arg(long, default_value_t = 1080)]
     height: u32,
 
+    /// The fractal to render.
+    #[arg(long, value_enum, default_value = "mandelbrot")]
+    fractal: FractalKind,
+
     /// Real part of the complex number at the center of the view.
     #[arg(long, allow_hyphen_values = true, default_value_t = -0.75)]
     center_re: f64,
@@ -63,6 +122,11 @@ struct Args {
     #[arg(long, default_value_t = 2.5)]
     scale: f64,
 
+    /// Counterclockwise rotation of the view around its center, in degrees.
+    #[arg(long, allow_hyphen_values = true, default_value_t = 0.0)]
+    #[serde(default)]
+    rotation: f64,
+
     /// Maximum number of iterations before a point is considered inside the set.
     #[arg(long, default_value_t = 100)]
     max_iterations: u64,
@@ -71,44 +135,383 @@ struct Args {
     #[arg(long, value_enum, default_value = "simple")]
     sampler: SamplerKind,
 
-    /// Number of samples per pixel. Only used by the stratified sampler; must be a perfect square.
+    /// Number of samples per pixel. Used by the stratified sampler, where it must be a perfect square
+    /// unless --samples-x and --samples-y are given, and by the multi-jittered sampler.
     #[arg(long, default_value_t = 16)]
     samples: u32,
 
+    /// Number of strata along the x axis for the stratified sampler. Use together with --samples-y
+    /// for a rectangular grid; overrides --samples.
+    #[arg(long, requires = "samples_y")]
+    samples_x: Option<u32>,
+
+    /// Number of strata along the y axis for the stratified sampler. Use together with --samples-x.
+    #[arg(long, requires = "samples_x")]
+    samples_y: Option<u32>,
+
     /// Reconstruction filter that weights samples.
     #[arg(long, value_enum, default_value = "box")]
     filter: FilterKind,
 
+    /// Radius of the filter's support along the x axis, in pixels. Defaults to the filter's own radius.
+    #[arg(long)]
+    filter_radius_x: Option<f64>,
+
+    /// Radius of the filter's support along the y axis, in pixels. Defaults to the filter's own radius.
+    #[arg(long)]
+    filter_radius_y: Option<f64>,
+
+    /// How the filter is evaluated during reconstruction: exactly, or approximated by looking up its
+    /// weights in precomputed per-axis tables.
+    #[arg(long, value_enum, default_value = "off")]
+    filter_table: FilterTable,
+
+    /// The B parameter of the Mitchell filter (blurring), between 0 and 1. Defaults to 1/3.
+    #[arg(long)]
+    mitchell_b: Option<f64>,
+
+    /// The C parameter of the Mitchell filter (ringing), between 0 and 1. Defaults to 1/3.
+    #[arg(long)]
+    mitchell_c: Option<f64>,
+
+    /// Falloff rate of the Gaussian filter: larger values give a narrower, sharper filter. Defaults
+    /// to 2.
+    #[arg(long)]
+    gaussian_alpha: Option<f64>,
+
+    /// Number of lobes that the window of the Lanczos filter stretches over. Defaults to 3.
+    #[arg(long)]
+    lanczos_tau: Option<f64>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    palette: PaletteArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    output: OutputArgs,
+
+    /// Also write the reconstructed values, before they are mapped to colors, to this value file.
+    /// Pixels inside the set are stored as NaN. See the `recolor` subcommand.
+    #[arg(long)]
+    #[serde(skip)]
+    export_values: Option<PathBuf>,
+
+    /// Cache the reconstructed values in this value file. If the file exists and was rendered with
+    /// the same settings, rendering is skipped and the cached values are colored with the current
+    /// palette; otherwise the image is rendered and the file is (re)written.
+    #[arg(long)]
+    #[serde(skip)]
+    cache: Option<PathBuf>,
+
+    /// Render in square tiles of this many pixels and write the image row by row as the tiles are
+    /// finished, so that memory use is bounded by the tile size instead of the image size. Use this
+    /// for very large images.
+    #[arg(long)]
+    tile_size: Option<u32>,
+
+    /// Read the options from the metadata of a PNG image rendered by this program, so that it can
+    /// be rendered again. Options given on the command line override the ones from the image.
+    #[arg(long, conflicts_with = "scene")]
+    #[serde(skip)]
+    from_image: Option<PathBuf>,
+
+    /// Read the options from a TOML scene file. Options given on the command line override the ones
+    /// from the file.
+    #[arg(long)]
+    #[serde(skip)]
+    scene: Option<PathBuf>,
+
+    /// Write the effective options, after combining the scene file or image with the command line,
+    /// as a scene file instead of rendering. Use - to write to standard output.
+    #[arg(long)]
+    #[serde(skip)]
+    dump_scene: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct AnimateArgs {
+    #[command(flatten)]
+    args: Args,
+
+    /// Number of frames, including the first and last. Required for a zoom; for a camera path it
+    /// defaults to the duration of the path times the frame rate, plus one.
+    #[arg(long)]
+    frames: Option<u32>,
+
+    /// Frame rate of the animation, in frames per second.
+    #[arg(long, default_value_t = 30.0)]
+    frame_rate: f64,
+
+    /// Stream the frames as uncompressed YUV4MPEG2 video to this file, or to standard output if it is
+    /// `-`, instead of writing them as images.
+    #[arg(long)]
+    y4m: Option<PathBuf>,
+
+    /// TOML file with the keyframes of a camera path, to render instead of a zoom.
+    #[arg(long, conflicts_with_all = ["to_center_re", "to_center_im", "to_scale", "to_max_iterations"])]
+    keyframes: Option<PathBuf>,
+
+    /// Real part of the center of the end view. Defaults to the start center.
+    #[arg(long, allow_hyphen_values = true)]
+    to_center_re: Option<f64>,
+
+    /// Imaginary part of the center of the end view. Defaults to the start center.
+    #[arg(long, allow_hyphen_values = true)]
+    to_center_im: Option<f64>,
+
+    /// Scale of the end view. Defaults to the start scale.
+    #[arg(long)]
+    to_scale: Option<f64>,
+
+    /// Maximum number of iterations in the end view, ramped up (or down) from --max-iterations over
+    /// the frames. Defaults to --max-iterations.
+    #[arg(long)]
+    to_max_iterations: Option<u64>,
+}
+
+#[derive(clap::Args)]
+struct CycleArgs {
+    #[command(flatten)]
+    args: Args,
+
+    /// Number of frames in one cycle through the palette.
+    #[arg(long, default_value_t = 30)]
+    frames: u32,
+
+    /// Frame rate of the animation, in frames per second.
+    #[arg(long, default_value_t = 30.0)]
+    frame_rate: f64,
+}
+
+#[derive(clap::Args)]
+struct PaletteCommandArgs {
+    #[command(flatten)]
+    args: Args,
+
+    /// Width of the palette strip in pixels.
+    #[arg(long, default_value_t = 1024)]
+    strip_width: u32,
+
+    /// Height of the palette strip in pixels.
+    #[arg(long, default_value_t = 128)]
+    strip_height: u32,
+
+    /// Render the image given by the render options, and show how its values are distributed over
+    /// the palette, after the transfer function.
+    #[arg(long)]
+    distribution: bool,
+
+    /// Write the palette as a TOML palette file to this path, or to standard output if it is `-`,
+    /// instead of drawing it.
+    #[arg(long, conflicts_with = "distribution")]
+    export: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct BatchArgs {
+    /// TOML file with a [[scenes]] list, or a directory of scene files.
+    scenes: PathBuf,
+
+    /// Directory that relative output paths of the scenes are resolved against.
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct RecolorArgs {
+    /// Value file written with --export-values or --cache.
+    values: PathBuf,
+
+    #[command(flatten)]
+    palette: PaletteArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Clone, clap::Args, Serialize, Deserialize)]
+struct PaletteArgs {
     /// Palette that maps iteration values to colors.
-    #[arg(long, value_enum, default_value = "rainbow")]
+    #[arg(long, value_enum, default_value = "rainbow", default_value_if("palette_name", ArgPredicate::IsPresent, "table"))]
     palette: PaletteKind,
 
-    /// TOML file with the color stops for the table palette. If omitted, a built-in default is used.
+    /// TOML file with the color stops for the table palette, or a Fractint color map (.map) or GIMP
+    /// gradient (.ggr). If omitted, a built-in default is used.
     #[arg(long)]
     palette_file: Option<PathBuf>,
 
-    /// Path of the output PNG image.
+    /// Name of a built-in palette to use for the table palette, instead of a palette file; implies
+    /// --palette table. Run the palettes subcommand to list them.
+    #[arg(long, conflicts_with = "palette_file")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette_name: Option<String>,
+
+    /// The color stops of the table palette, when they are embedded rather than in a palette file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stops: Option<Vec<StopEntry>>,
+
+    /// The color space of the table palette, when it is embedded rather than in a palette file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color_space: Option<ColorSpace>,
+
+    /// The interpolation of the table palette, when it is embedded rather than in a palette file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interpolation: Option<Interpolation>,
+
+    /// Range of values to spread the palette over, as MIN..MAX, instead of the whole range of values,
+    /// 0..1. Values outside of it are black, unless --palette-edge brings them in.
+    #[arg(long, allow_hyphen_values = true)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette_range: Option<String>,
+
+    /// Phase offset of the palette, as a fraction of the palette. Shifting it from 0 to 1 cycles the
+    /// colors through the palette once.
+    #[arg(long, allow_hyphen_values = true, default_value_t = 0.0)]
+    #[serde(default)]
+    palette_offset: f64,
+
+    /// Repeat the palette every this many iterations, rather than spreading it over the whole range
+    /// of values once. Useful for deep zooms, where the values only cover a sliver of the range.
+    /// The period applies after the transfer function: with another transfer than linear, it is in
+    /// transferred values times the maximum number of iterations. Not available with histogram.
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette_period: Option<f64>,
+
+    /// How values outside of the palette's range get a color. Defaults to wrap when --palette-offset
+    /// or --palette-period is given; otherwise such values are black.
+    #[arg(long, value_enum)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette_edge: Option<Edge>,
+
+    /// Transfer function that reshapes the values before they are mapped to colors.
+    #[arg(long, value_enum, default_value = "linear")]
+    #[serde(default)]
+    transfer: TransferFunction,
+
+    /// Exponent of the power transfer function. Values below 1 spread the palette over the low values,
+    /// which are the most common; values above 1 over the high values.
+    #[arg(long, default_value_t = 0.5)]
+    #[serde(default = "default_transfer_gamma")]
+    transfer_gamma: f64,
+}
+
+fn default_transfer_gamma() -> f64 {
+    0.5
+}
+
+#[derive(Clone, clap::Args, Serialize, Deserialize)]
+struct OutputArgs {
+    /// Path of the output image.
     #[arg(short, long, default_value = "mandelbrot.png")]
     output: PathBuf,
+
+    /// File format of the output image. Defaults to the format that matches the extension of the
+    /// output path, or PNG.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Number of bits per color channel: 8 or 16 for PNG, 32 (floating point) for EXR and HDR.
+    /// Defaults to 8 for PNG and 32 for the floating point formats.
+    #[arg(long, value_enum)]
+    bit_depth: Option<BitDepth>,
+
+    /// Write an alpha channel (PNG and EXR only). The interior of the set gets --interior-alpha,
+    /// pixels on the edge of the set are as opaque as the fraction of their samples outside of it,
+    /// and palette stops can have an alpha of their own.
+    #[arg(long)]
+    #[serde(default)]
+    alpha: bool,
+
+    /// Opacity of the interior of the set when an alpha channel is written, from 0 (transparent) to
+    /// 1 (opaque, as without an alpha channel).
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    interior_alpha: f64,
 }
 
-#[derive(Clone, Copy, ValueEnum)]
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum FractalKind {
+    /// The Mandelbrot set.
+    Mandelbrot,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum SamplerKind {
     /// A single sample at the center of each pixel (fast, no anti-aliasing).
     Simple,
-    /// A jittered grid of samples per pixel (see --samples).
+    /// A jittered grid of samples per pixel (see --samples, --samples-x and --samples-y).
     Stratified,
+    /// Correlated multi-jittered samples: stratified in 2D and along each axis, for any --samples.
+    MultiJittered,
 }
 
-#[derive(Clone, Copy, ValueEnum)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum FilterKind {
     /// Box filter: samples within the pixel are weighted equally (fast).
     Box,
     /// Mitchell-Netravali filter: higher quality; also gathers samples from neighboring pixels.
     Mitchell,
+    /// Gaussian filter: smooth, slightly soft, no ringing.
+    Gaussian,
+    /// Lanczos windowed-sinc filter: sharpest, but may ring around hard edges.
+    Lanczos,
+    /// Catmull-Rom filter: sharper than Mitchell, with a little more ringing.
+    CatmullRom,
+    /// Triangle (tent) filter: cheap, a little softer than box but without its blockiness.
+    Triangle,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum FilterTable {
+    /// Evaluate the filter function for every sample.
+    Off,
+    /// Look up the weight of the nearest table entry.
+    Nearest,
+    /// Interpolate linearly between table entries.
+    Linear,
+}
+
+/// Number of entries in each axis of a filter lookup table.
+const FILTER_TABLE_SIZE: usize = 256;
+
+/// Default B and C parameters of the Mitchell filter, as recommended by Mitchell and Netravali.
+const DEFAULT_MITCHELL_B: f64 = 1.0 / 3.0;
+const DEFAULT_MITCHELL_C: f64 = 1.0 / 3.0;
+
+/// Default falloff rate of the Gaussian filter.
+const DEFAULT_GAUSSIAN_ALPHA: f64 = 2.0;
+
+/// Default number of lobes of the Lanczos window.
+const DEFAULT_LANCZOS_TAU: f64 = 3.0;
+
+/// Upper limit for the filter radius. Reconstruction gathers samples from a square of pixels around
+/// each pixel, so its cost grows with the square of the radius.
+const MAX_FILTER_RADIUS: f64 = 16.0;
+
+impl FilterKind {
+    /// The radius of the filter's support when no radius is given on the command line.
+    fn default_radius(self) -> (f64, f64) {
+        match self {
+            FilterKind::Box => (0.5, 0.5),
+            FilterKind::Triangle => (1.0, 1.0),
+            FilterKind::Gaussian => (1.5, 1.5),
+            FilterKind::Mitchell | FilterKind::CatmullRom => (2.0, 2.0),
+            FilterKind::Lanczos => (3.0, 3.0),
+        }
+    }
 }
 
-#[derive(Clone, Copy, ValueEnum)]
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum PaletteKind {
     /// Colors interpolated between configurable stops (see --palette-file).
     Table,
@@ -118,115 +521,851 @@ enum PaletteKind {
     Rainbow,
 }
 
+/// Keyword of the PNG text chunk that holds the options an image was rendered with.
+const OPTIONS_KEYWORD: &str = "mandelbrot-options";
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    match cli.command {
+        Some(Command::Recolor(args)) => recolor(&args),
+        Some(Command::Batch(args)) => batch::render_batch(&args.scenes, args.output_dir.as_deref()),
+        Some(Command::Animate(animate)) => {
+            let matches = matches.subcommand_matches("animate").expect("animate arguments");
+            let args = resolve_args(&animate.args, matches)?;
+            render_animation(&args, &animate)
+        }
+        Some(Command::Cycle(cycle)) => {
+            let matches = matches.subcommand_matches("cycle").expect("cycle arguments");
+            let args = resolve_args(&cycle.args, matches)?;
+            if args.dump_scene.is_some() {
+                return Err("--dump-scene cannot be used for animations".into());
+            }
+            animation::cycle_palette(&args, cycle.frames, cycle.frame_rate)
+        }
+        Some(Command::Palettes) => {
+            library::list_palettes();
+            Ok(())
+        }
+        Some(Command::Palette(palette)) => {
+            let matches = matches.subcommand_matches("palette").expect("palette arguments");
+            let mut args = resolve_args(&palette.args, matches)?;
+            if args.dump_scene.is_some() {
+                return Err("--dump-scene cannot be used for palettes".into());
+            }
+            match &palette.export {
+                Some(path) => preview::export_palette(&args.palette, path),
+                None => {
+                    // The strip goes to its own file, not to the output of a scene or the default image.
+                    if matches.value_source("output") != Some(ValueSource::CommandLine) {
+                        args.output.output = PathBuf::from("palette.png");
+                    }
+                    preview::draw_strip(&args, palette.strip_width, palette.strip_height, palette.distribution)
+                }
+            }
+        }
+        None => {
+            let args = resolve_args(&cli.args, &matches)?;
+            match &args.dump_scene {
+                Some(path) => dump_scene(&args, path),
+                None => render(&args),
+            }
+        }
+    }
+}
+
+/// Returns the arguments to render with: read from an image or scene file if one is given, and
+/// overridden by the options given explicitly on the command line.
+fn resolve_args(args: &Args, matches: &ArgMatches) -> Result<Args, Box<dyn Error>> {
+    if let Some(path) = &args.from_image {
+        args_from_image(path, args, matches)
+    } else if let Some(path) = &args.scene {
+        args_from_scene(path, args, matches)
+    } else {
+        Ok(args.clone())
+    }
+}
+
+/// Reads the options embedded in an image, and overrides them with the options that were given
+/// explicitly on the command line.
+fn args_from_image(path: &Path, args: &Args, matches: &ArgMatches) -> Result<Args, Box<dyn Error>> {
+    let text = read_png_text(path, OPTIONS_KEYWORD)?.ok_or_else(|| format!("image {} does not contain rendering options", path.display()))?;
+    let mut embedded: toml::Table = toml::from_str(&text).map_err(|e| format!("cannot parse the options in image {}: {e}", path.display()))?;
+
+    // Rendering again should not overwrite the image the options came from, so the output path
+    // is not taken over.
+    embedded.remove("output");
+
+    merge_options(args, matches, embedded).map_err(|e| format!("invalid options in image {}: {e}", path.display()).into())
+}
+
+/// Reads a scene file, and overrides its options with the options that were given explicitly on
+/// the command line.
+fn args_from_scene(path: &Path, args: &Args, matches: &ArgMatches) -> Result<Args, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read scene file {}: {e}", path.display()))?;
+    let scene: toml::Table = toml::from_str(&text).map_err(|e| format!("cannot parse scene file {}: {e}", path.display()))?;
+    scene_to_args(scene, path.parent().unwrap_or(Path::new("")), args, matches).map_err(|e| format!("invalid scene file {}: {e}", path.display()).into())
+}
+
+/// Turns the options of a scene into arguments. A relative palette file path is resolved against
+/// `base_dir`, the directory of the scene file, so that scenes can refer to palettes next to them.
+fn scene_to_args(mut scene: toml::Table, base_dir: &Path, args: &Args, matches: &ArgMatches) -> Result<Args, Box<dyn Error>> {
+    let command = Cli::command();
+    for key in scene.keys() {
+        let known = TABLE_PALETTE_OPTIONS.contains(&key.as_str()) || command.get_arguments().any(|arg| arg.get_id() == key.as_str());
+        if !known || NON_SCENE_OPTIONS.contains(&key.as_str()) {
+            return Err(format!("unknown option '{key}'").into());
+        }
+    }
+
+    if let Some(toml::Value::String(palette_file)) = scene.get_mut("palette_file") {
+        *palette_file = base_dir.join(&*palette_file).to_string_lossy().into_owned();
+    }
+
+    merge_options(args, matches, scene)
+}
+
+/// Options of the table palette that are not command line options, but can be given in a scene
+/// instead of a palette file, and are embedded in images.
+const TABLE_PALETTE_OPTIONS: [&str; 3] = ["stops", "color_space", "interpolation"];
+
+/// Options that only control what happens with a single invocation, rather than describing a render,
+/// and are therefore not part of a scene.
+const NON_SCENE_OPTIONS: [&str; 5] = ["export_values", "cache", "from_image", "scene", "dump_scene"];
+
+/// Combines options read from a file with the arguments: an option given explicitly on the command
+/// line wins, otherwise the value from the file is used, and options missing from both get their
+/// default values. The options that are not part of a scene are always taken from the arguments.
+fn merge_options(args: &Args, matches: &ArgMatches, mut options: toml::Table) -> Result<Args, Box<dyn Error>> {
+    // An explicit palette file or palette name replaces the table palette from the file, and a
+    // palette name also selects the table palette.
+    if given_on_command_line(matches, "palette_file") || given_on_command_line(matches, "palette_name") {
+        for key in TABLE_PALETTE_OPTIONS.into_iter().chain(["palette_file", "palette_name"]) {
+            options.remove(key);
+        }
+    }
+    if given_on_command_line(matches, "palette_name") {
+        options.remove("palette");
+    }
+
+    let mut merged = toml::Table::try_from(args)?;
+    for (key, value) in options {
+        if !given_on_command_line(matches, &key) {
+            merged.insert(key, value);
+        }
+    }
+
+    let mut merged: Args = merged.try_into()?;
+    merged.export_values = args.export_values.clone();
+    merged.cache = args.cache.clone();
+    merged.dump_scene = args.dump_scene.clone();
+    Ok(merged)
+}
+
+fn given_on_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches.ids().any(|present| present == id) && matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+/// Writes the effective options as a scene file, or to standard output if `path` is `-`.
+///
+/// The palette file is written as an absolute path: a relative one would be resolved against the
+/// directory of the scene file when it is loaded, rather than against the current directory.
+fn dump_scene(args: &Args, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut args = args.clone();
+    if let Some(palette_file) = &mut args.palette.palette_file {
+        *palette_file = std::path::absolute(&*palette_file).map_err(|e| format!("cannot resolve palette file {}: {e}", palette_file.display()))?;
+    }
+    let scene = toml::to_string(&args)?;
+    if path == Path::new("-") {
+        print!("{scene}");
+    } else {
+        std::fs::write(path, scene).map_err(|e| format!("cannot write scene file {}: {e}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Describes the options for the metadata of the output image. For the table palette, the color
+/// stops are embedded as well, so that the image can be rendered again without the palette file.
+fn options_text(args: &Args) -> Result<Vec<TextChunk>, Box<dyn Error>> {
+    let mut args = args.clone();
+    if matches!(args.palette.palette, PaletteKind::Table) {
+        let table = table_palette(&args.palette)?;
+        args.palette.stops = Some(table.stops);
+        args.palette.color_space = Some(table.color_space);
+        args.palette.interpolation = Some(table.interpolation);
+    }
+    let options = toml::to_string(&args)?;
+    Ok(vec![("Software".to_string(), format!("mandelbrot {}", env!("CARGO_PKG_VERSION"))), (OPTIONS_KEYWORD.to_string(), options)])
+}
+
+fn render(args: &Args) -> Result<(), Box<dyn Error>> {
+    render_to(args, None)
+}
 
-    // Validate up front for a clean error, rather than letting StratifiedSampler's assertion panic
-    // inside a worker thread once rendering has started.
-    if matches!(args.sampler, SamplerKind::Stratified) {
-        let side = (args.samples as f64).sqrt().round() as u32;
-        if side * side != args.samples {
-            return Err(format!("--samples must be a perfect square for the stratified sampler, got {}", args.samples).into());
+/// Renders an image, and writes it to the output file, or passes it on to `sink` if given.
+fn render_to(args: &Args, sink: Option<Sink>) -> Result<(), Box<dyn Error>> {
+    // Validate up front for a clean error, rather than letting a sampler's assertion panic inside a
+    // worker thread once rendering has started.
+    let strata = match (args.sampler, args.samples_x, args.samples_y) {
+        (SamplerKind::Stratified, Some(samples_x), Some(samples_y)) => (samples_x, samples_y),
+        (_, None, None) => {
+            let side = (args.samples as f64).sqrt().round() as u32;
+            if matches!(args.sampler, SamplerKind::Stratified) && side * side != args.samples {
+                return Err(format!("--samples must be a perfect square for the stratified sampler, got {}; use --samples-x and --samples-y for a rectangular grid", args.samples).into());
+            }
+            (side, side)
+        }
+        (SamplerKind::Stratified, _, _) => return Err("--samples-x and --samples-y must be given together".into()),
+        _ => return Err("--samples-x and --samples-y are only used by the stratified sampler".into()),
+    };
+    let positive = match args.sampler {
+        // The simple sampler always takes one sample per pixel, whatever --samples says.
+        SamplerKind::Simple => true,
+        SamplerKind::Stratified => strata.0 > 0 && strata.1 > 0,
+        SamplerKind::MultiJittered => args.samples > 0,
+    };
+    if !positive {
+        return Err("the number of samples per pixel must be positive".into());
+    }
+    let sampler = SamplerConfig { kind: args.sampler, samples: args.samples, strata };
+
+    let (default_radius_x, default_radius_y) = args.filter.default_radius();
+    let radius_x = args.filter_radius_x.unwrap_or(default_radius_x);
+    let radius_y = args.filter_radius_y.unwrap_or(default_radius_y);
+    for (name, radius) in [("--filter-radius-x", radius_x), ("--filter-radius-y", radius_y)] {
+        if !(radius > 0.0 && radius <= MAX_FILTER_RADIUS) {
+            return Err(format!("{name} must be greater than 0 and at most {MAX_FILTER_RADIUS}, got {radius}").into());
+        }
+    }
+    let filter_parameters = [
+        ("--mitchell-b", args.mitchell_b, FilterKind::Mitchell),
+        ("--mitchell-c", args.mitchell_c, FilterKind::Mitchell),
+        ("--gaussian-alpha", args.gaussian_alpha, FilterKind::Gaussian),
+        ("--lanczos-tau", args.lanczos_tau, FilterKind::Lanczos),
+    ];
+    for (name, value, filter) in filter_parameters {
+        if value.is_some() && args.filter != filter {
+            return Err(format!("{name} only applies to the {} filter", filter.to_possible_value().expect("filter name").get_name()).into());
+        }
+    }
+    let mitchell_b = args.mitchell_b.unwrap_or(DEFAULT_MITCHELL_B);
+    let mitchell_c = args.mitchell_c.unwrap_or(DEFAULT_MITCHELL_C);
+    for (name, value) in [("--mitchell-b", mitchell_b), ("--mitchell-c", mitchell_c)] {
+        if !(0.0..=1.0).contains(&value) {
+            return Err(format!("{name} must be between 0 and 1, got {value}").into());
+        }
+    }
+    let gaussian_alpha = args.gaussian_alpha.unwrap_or(DEFAULT_GAUSSIAN_ALPHA);
+    let lanczos_tau = args.lanczos_tau.unwrap_or(DEFAULT_LANCZOS_TAU);
+    for (name, value) in [("--gaussian-alpha", gaussian_alpha), ("--lanczos-tau", lanczos_tau)] {
+        if !(value > 0.0 && value.is_finite()) {
+            return Err(format!("{name} must be positive, got {value}").into());
         }
     }
 
-    let palette = build_palette(args.palette, args.palette_file.as_deref())?;
-    let value_to_color = |value| match value {
-        Some(value) => palette.evaluate(value),
-        None => Rgb([0, 0, 0]),
+    let (format, bit_depth) = args.output.resolve()?;
+    if args.tile_size == Some(0) {
+        return Err("--tile-size must be positive".into());
+    }
+    if args.tile_size.is_some() && format != Format::Png {
+        return Err("--tile-size is only supported for PNG output".into());
+    }
+    if args.tile_size.is_some() && (args.export_values.is_some() || args.cache.is_some()) {
+        return Err("--export-values and --cache cannot be combined with --tile-size".into());
+    }
+    if sink.is_some() && args.tile_size.is_some() {
+        return Err("--tile-size cannot be used for animations".into());
+    }
+    if args.tile_size.is_some() && args.palette.transfer == TransferFunction::Histogram {
+        return Err("--transfer histogram needs the whole image and cannot be combined with --tile-size".into());
+    }
+    if args.output.alpha && args.tile_size.is_some() {
+        return Err("--alpha cannot be combined with --tile-size".into());
+    }
+    if args.output.alpha && matches!(sink, Some(Sink::Video(_))) {
+        return Err("--alpha cannot be used for video output".into());
+    }
+    if matches!(sink, Some(Sink::Video(_))) && args.cache.is_some() {
+        return Err("--cache cannot be used for video output".into());
+    }
+    let view = View { center_re: args.center_re, center_im: args.center_im, scale: args.scale, max_iterations: args.max_iterations, rotation: args.rotation };
+
+    // Everything that affects the reconstructed values goes into the fingerprint, so that a cache
+    // file is only reused for an identical render.
+    let mut settings = format!(
+        "{}x{} {:?} {view:?} {:?} {} {strata:?} {:?} {radius_x} {radius_y} {:?} {mitchell_b} {mitchell_c} {gaussian_alpha} {lanczos_tau}",
+        args.width, args.height, args.fractal, args.sampler, args.samples, args.filter, args.filter_table,
+    );
+    // With an alpha channel, value files also hold the coverage of each pixel.
+    if args.output.alpha {
+        settings.push_str(" coverage");
+    }
+    let fingerprint = values::fingerprint(&settings);
+
+    let palette = build_palette(&args.palette, args.max_iterations)?;
+    let transfer = build_transfer(&args.palette, args.max_iterations)?;
+
+    if let Some(path) = &args.cache && path.exists() {
+        let file = ValueFile::read(path)?;
+        if file.fingerprint() == fingerprint {
+            eprintln!("Using cached values from {}", path.display());
+            if let Some(export_path) = &args.export_values {
+                file.write(export_path)?;
+            }
+            if let Some(Sink::Values(values)) = sink {
+                *values.borrow_mut() = Some(file);
+                return Ok(());
+            }
+            return color_value_file(&file, &transfer, palette.as_ref(), &args.output, &options_text(args)?);
+        }
+        eprintln!("Cache {} was rendered with different settings; rendering again", path.display());
+    }
+    let output = Output {
+        path: args.output.output.clone(),
+        format,
+        bit_depth,
+        width: args.width,
+        height: args.height,
+        tile_size: args.tile_size,
+        value_files: args.export_values.iter().chain(&args.cache).cloned().collect(),
+        view,
+        fingerprint,
+        text: options_text(args)?,
+        transfer,
+        alpha: args.output.alpha.then_some(args.output.interior_alpha as f32),
+        sink,
     };
 
+    let value_to_color = |value| color_or_background(palette.as_ref(), value);
+
     let center = Complex64::new(args.center_re, args.center_im);
-    let renderer = MandelbrotRenderer::new(center, args.scale, args.max_iterations, args.width, args.height);
+    let renderer = match args.fractal {
+        FractalKind::Mandelbrot => MandelbrotRenderer::new(center, args.scale, args.rotation, args.max_iterations, args.width, args.height),
+    };
 
     // Select the filter and sampler at runtime, but keep them statically dispatched: each match arm
-    // instantiates render_image with concrete types, so there are no virtual calls in the hot loop.
-    let image = match args.filter {
+    // instantiates render_to_output with concrete types, so there are no virtual calls in the hot loop.
+    match args.filter {
         FilterKind::Box => {
-            let filter = BoxFilter::with_defaults();
-            render_with_sampler(args.sampler, args.samples, &renderer, &filter, &value_to_color, args.width, args.height)
+            let filter = BoxFilter::new(radius_x, radius_y);
+            render_with_filter(&filter, args.filter_table, &sampler, &renderer, &value_to_color, &output)
         }
         FilterKind::Mitchell => {
-            let filter = MitchellFilter::with_defaults();
-            render_with_sampler(args.sampler, args.samples, &renderer, &filter, &value_to_color, args.width, args.height)
+            let filter = MitchellFilter::new(radius_x, radius_y, mitchell_b, mitchell_c);
+            render_with_filter(&filter, args.filter_table, &sampler, &renderer, &value_to_color, &output)
+        }
+        FilterKind::Gaussian => {
+            let filter = GaussianFilter::new(radius_x, radius_y, gaussian_alpha);
+            render_with_filter(&filter, args.filter_table, &sampler, &renderer, &value_to_color, &output)
+        }
+        FilterKind::Lanczos => {
+            let filter = LanczosFilter::new(radius_x, radius_y, lanczos_tau);
+            render_with_filter(&filter, args.filter_table, &sampler, &renderer, &value_to_color, &output)
+        }
+        FilterKind::CatmullRom => {
+            let filter = CatmullRomFilter::new(radius_x, radius_y);
+            render_with_filter(&filter, args.filter_table, &sampler, &renderer, &value_to_color, &output)
+        }
+        FilterKind::Triangle => {
+            let filter = TriangleFilter::new(radius_x, radius_y);
+            render_with_filter(&filter, args.filter_table, &sampler, &renderer, &value_to_color, &output)
+        }
+    }
+}
+
+/// Renders the frames of an animation: a camera path through the keyframes if a keyframe file is
+/// given, and otherwise a zoom from the view in the arguments to the end view.
+fn render_animation(args: &Args, animate: &AnimateArgs) -> Result<(), Box<dyn Error>> {
+    if args.dump_scene.is_some() {
+        return Err("--dump-scene cannot be used for animations".into());
+    }
+    if animate.frames == Some(0) {
+        return Err("--frames must be positive".into());
+    }
+    if !(animate.frame_rate > 0.0 && animate.frame_rate.is_finite()) {
+        return Err(format!("--frame-rate must be positive, got {}", animate.frame_rate).into());
+    }
+
+    let frames = match &animate.keyframes {
+        Some(path) => {
+            let keyframes = animation::read_keyframes(path)?;
+            let frame_count = animate.frames.unwrap_or_else(|| (animation::duration(&keyframes) * animate.frame_rate).round() as u32 + 1);
+            animation::camera_path(&keyframes, args.max_iterations, frame_count)
+        }
+        None => {
+            let start = Frame::from_args(args);
+            let end = Frame {
+                center_re: animate.to_center_re.unwrap_or(start.center_re),
+                center_im: animate.to_center_im.unwrap_or(start.center_im),
+                scale: animate.to_scale.unwrap_or(start.scale),
+                max_iterations: animate.to_max_iterations.unwrap_or(start.max_iterations),
+                ..start
+            };
+            if !(start.scale > 0.0 && end.scale > 0.0) {
+                return Err("the start and end scales must be positive".into());
+            }
+            let frame_count = animate.frames.ok_or("--frames is required for a zoom")?;
+            animation::zoom(start, end, frame_count)
         }
     };
 
-    image.save(&args.output)?;
-    Ok(())
+    animation::render_frames(args, &frames, animate.y4m.as_deref(), animate.frame_rate)
+}
+
+/// Colors the values in a value file with a palette, and writes the resulting image.
+fn recolor(args: &RecolorArgs) -> Result<(), Box<dyn Error>> {
+    let file = ValueFile::read(&args.values)?;
+    if file.channels() > 2 {
+        return Err(format!("value file {} has {} channels, only files with values and optionally coverage can be colored", args.values.display(), file.channels()).into());
+    }
+
+    let palette = build_palette(&args.palette, file.view().max_iterations)?;
+    let transfer = build_transfer(&args.palette, file.view().max_iterations)?;
+    color_value_file(&file, &transfer, palette.as_ref(), &args.output, &[])
+}
+
+/// Colors the values in a value file, and writes the image to the output file. With an alpha
+/// channel, the coverage in the value file gives the opacity of the edges of the set, if it has one.
+fn color_value_file(file: &ValueFile, transfer: &Transfer, palette: &(dyn Palette + Sync), output: &OutputArgs, text: &[TextChunk]) -> Result<(), Box<dyn Error>> {
+    let (format, bit_depth) = output.resolve()?;
+    let values = transfer.apply_all(&value_file_values(file));
+    let (width, height) = (file.width(), file.height());
+    if output.alpha {
+        let image = colorize_rgba(&values, &value_file_coverage(file), width, height, &|value| color_or_background(palette, value), output.interior_alpha as f32);
+        save_rgba_image(&image, &output.output, format, bit_depth, text)
+    } else {
+        save_image(&colorize_values(&values, width, height, palette), &output.output, format, bit_depth, text)
+    }
+}
+
+/// Returns the values in a value file, with None for the pixels without a value.
+fn value_file_values(file: &ValueFile) -> Vec<Option<f64>> {
+    file.values().chunks_exact(file.channels() as usize).map(|pixel| (!pixel[0].is_nan()).then_some(pixel[0])).collect()
+}
+
+/// Returns the coverage of the pixels in a value file: the second channel if it has one, and
+/// otherwise full coverage.
+fn value_file_coverage(file: &ValueFile) -> Vec<f32> {
+    file.values().chunks_exact(file.channels() as usize).map(|pixel| pixel.get(1).map_or(1.0, |&coverage| coverage as f32)).collect()
+}
+
+/// Colors transferred values, in row-major order, with a palette.
+fn colorize_values(values: &[Option<f64>], width: u32, height: u32, palette: &(dyn Palette + Sync)) -> Rgb32FImage {
+    colorize(values, width, height, &|value| color_or_background(palette, value))
+}
+
+impl OutputArgs {
+    /// Determines the output format and bit depth, and checks that they go together.
+    fn resolve(&self) -> Result<(Format, BitDepth), Box<dyn Error>> {
+        let format = self.format.or_else(|| Format::from_path(&self.output)).unwrap_or(Format::Png);
+        let bit_depth = self.bit_depth.unwrap_or(format.default_bit_depth());
+        if !format.supports(bit_depth) {
+            return Err("--bit-depth must be 8 or 16 for PNG output, 8 for GIF output, and 32 for EXR and HDR output".into());
+        }
+        if self.alpha && !matches!(format, Format::Png | Format::Exr) {
+            return Err("--alpha is only supported for PNG and EXR output".into());
+        }
+        if !(0.0..=1.0).contains(&self.interior_alpha) {
+            return Err(format!("--interior-alpha must be between 0 and 1, got {}", self.interior_alpha).into());
+        }
+        Ok((format, bit_depth))
+    }
 }
 
 // ===== Palette construction ==================================================================================================================================
 
-fn build_palette(kind: PaletteKind, palette_file: Option<&Path>) -> Result<Box<dyn Palette + Sync>, Box<dyn Error>> {
-    let palette: Box<dyn Palette + Sync> = match kind {
+/// Builds the palette, with the mapping of values onto it. `max_iterations` is the maximum number of
+/// iterations that the values are normalized by.
+fn build_palette(args: &PaletteArgs, max_iterations: u64) -> Result<Box<dyn Palette + Sync>, Box<dyn Error>> {
+    check_palette_name(args)?;
+    let range = palette_range(args)?;
+    let palette: Box<dyn Palette + Sync> = match args.palette {
         PaletteKind::Table => {
-            let entries = match palette_file {
-                Some(path) => load_table_entries(path)?,
-                None => default_table_entries(),
-            };
-            Box::new(TablePalette::new(entries))
+            let table = table_palette(args)?;
+            let entries = table.stops.iter().map(stop_entry).collect::<Result<_, String>>()?;
+            Box::new(TablePalette::new(entries, range, table.color_space, table.interpolation))
+        }
+        PaletteKind::Grayscale => Box::new(Grayscale::new(range)),
+        PaletteKind::Rainbow => Box::new(Rainbow::new(range)),
+    };
+
+    // Without any mapping options, values are passed to the palette exactly as they are.
+    if args.palette_offset == 0.0 && args.palette_period.is_none() && args.palette_edge.is_none() {
+        return Ok(palette);
+    }
+    if let Some(period) = args.palette_period && !(period > 0.0 && period.is_finite()) {
+        return Err(format!("--palette-period must be positive, got {period}").into());
+    }
+    if args.palette_period.is_some() && args.transfer == TransferFunction::Histogram {
+        return Err("--palette-period cannot be combined with --transfer histogram, whose values are fractions of the image rather than iterations".into());
+    }
+    let period = args.palette_period.map(|period| period / max_iterations as f64);
+    Ok(Box::new(Mapping::new(palette, period, args.palette_offset, args.palette_edge.unwrap_or(Edge::Wrap))))
+}
+
+/// Returns the range of values that the palette is spread over: the range given with
+/// `--palette-range`, or else `0.0..1.0`.
+fn palette_range(args: &PaletteArgs) -> Result<Range<f64>, Box<dyn Error>> {
+    let Some(text) = &args.palette_range else {
+        return Ok(0.0..1.0);
+    };
+    let invalid = || format!("--palette-range must be MIN..MAX, with MIN less than MAX, got '{text}'");
+    let (start, end) = text.split_once("..").ok_or_else(invalid)?;
+    let start: f64 = start.trim().parse().map_err(|_| invalid())?;
+    let end: f64 = end.trim().parse().map_err(|_| invalid())?;
+    if !(start.is_finite() && end.is_finite() && start < end) {
+        return Err(invalid().into());
+    }
+    Ok(start..end)
+}
+
+/// Checks that a palette name is only given for the table palette, as the other palettes have no
+/// stops that it could replace.
+fn check_palette_name(args: &PaletteArgs) -> Result<(), Box<dyn Error>> {
+    if args.palette_name.is_some() && !matches!(args.palette, PaletteKind::Table) {
+        return Err("--palette-name selects the stops of the table palette, and cannot be used with another palette".into());
+    }
+    Ok(())
+}
+
+/// Builds the transfer function that reshapes the values before they go to the palette.
+/// `max_iterations` is the maximum number of iterations that the values are normalized by.
+fn build_transfer(args: &PaletteArgs, max_iterations: u64) -> Result<Transfer, Box<dyn Error>> {
+    if args.transfer == TransferFunction::Power && !(args.transfer_gamma > 0.0 && args.transfer_gamma.is_finite()) {
+        return Err(format!("--transfer-gamma must be positive, got {}", args.transfer_gamma).into());
+    }
+    Ok(Transfer::new(args.transfer, args.transfer_gamma, max_iterations))
+}
+
+/// Maps a reconstructed value to a color and opacity. Pixels without a value (inside the set) are
+/// black.
+fn color_or_background(palette: &(dyn Palette + Sync), value: Option<f64>) -> Rgba<f32> {
+    match value {
+        Some(value) => {
+            let Rgb([r, g, b]) = palette.evaluate(value);
+            Rgba([r, g, b, palette.alpha(value)])
+        }
+        None => Rgba([0.0, 0.0, 0.0, 1.0]),
+    }
+}
+
+/// Returns the table palette: the color stops embedded in the arguments (when they were read from an
+/// image or a scene), or else the stops from the palette file, or else the named built-in palette,
+/// or else the built-in default. A color space or interpolation in the arguments overrides the one
+/// of the palette file.
+fn table_palette(args: &PaletteArgs) -> Result<PaletteFile, Box<dyn Error>> {
+    let mut table = match (&args.stops, &args.palette_file, &args.palette_name) {
+        (Some(stops), _, _) => {
+            validate_stops(stops, |index, _| format!("stop {}", index + 1)).map_err(|e| format!("invalid palette stops: {e}"))?;
+            PaletteFile { stops: stops.clone(), color_space: ColorSpace::default(), interpolation: Interpolation::default() }
         }
-        PaletteKind::Grayscale => Box::new(Grayscale::new(0.0..1.0)),
-        PaletteKind::Rainbow => Box::new(Rainbow::new(0.0..1.0)),
+        (None, Some(path), _) => load_palette_file(path)?,
+        (None, None, Some(name)) => library::builtin_palette(name)?,
+        (None, None, None) => PaletteFile { stops: default_table_stops(), color_space: ColorSpace::default(), interpolation: Interpolation::default() },
     };
-    Ok(palette)
-}
-
-fn default_table_entries() -> Vec<Entry> {
-    vec![
-        Entry::new(0.000, Rgb([0x00, 0x00, 0x66])),
-        Entry::new(0.010, Rgb([0x19, 0x19, 0x19])),
-        Entry::new(0.018, Rgb([0xFF, 0xFF, 0x4C])),
-        Entry::new(0.022, Rgb([0x00, 0x66, 0x00])),
-        Entry::new(0.040, Rgb([0xFF, 0xFF, 0xFF])),
-        Entry::new(0.200, Rgb([0x00, 0x00, 0x99])),
-        Entry::new(0.500, Rgb([0x00, 0x00, 0x00])),
-        Entry::new(1.000, Rgb([0xFF, 0xFF, 0xFF])),
+    if let Some(color_space) = args.color_space {
+        table.color_space = color_space;
+    }
+    if let Some(interpolation) = args.interpolation {
+        table.interpolation = interpolation;
+    }
+    Ok(table)
+}
+
+fn default_table_stops() -> Vec<StopEntry> {
+    [
+        (0.000, "#000066"),
+        (0.010, "#191919"),
+        (0.018, "#FFFF4C"),
+        (0.022, "#006600"),
+        (0.040, "#FFFFFF"),
+        (0.200, "#000099"),
+        (0.500, "#000000"),
+        (1.000, "#FFFFFF"),
     ]
+    .into_iter()
+    .map(|(value, color)| StopEntry { value, color: color.to_string(), alpha: None })
+    .collect()
 }
 
 /// Deserialized form of a palette file: a list of `[[stops]]` tables, each with a `value` and a
-/// `#RRGGBB` `color`.
+/// `color` (see [`parse_color`]), and the color space and curve to interpolate with.
 #[derive(Deserialize)]
 struct PaletteFile {
     stops: Vec<StopEntry>,
+    #[serde(default)]
+    color_space: ColorSpace,
+    #[serde(default)]
+    interpolation: Interpolation,
 }
 
+/// A TOML palette file as written, with the position of each stop and its fields in the text, so
+/// that errors can point at the offending line.
 #[derive(Deserialize)]
+struct PaletteFileText {
+    stops: Vec<toml::Spanned<StopEntryText>>,
+    #[serde(default)]
+    color_space: ColorSpace,
+    #[serde(default)]
+    interpolation: Interpolation,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 struct StopEntry {
     value: f64,
     color: String,
+    /// Opacity of the stop, which overrides the alpha of a `#RRGGBBAA` color.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alpha: Option<f64>,
+}
+
+/// A color stop as written in a TOML palette file, with the position of each field in the text.
+#[derive(Deserialize)]
+struct StopEntryText {
+    value: toml::Spanned<f64>,
+    color: toml::Spanned<String>,
+    #[serde(default)]
+    alpha: Option<toml::Spanned<f64>>,
+}
+
+/// The part of a color stop that an error message points at.
+#[derive(Clone, Copy)]
+enum StopField {
+    Stop,
+    Value,
+    Color,
+    Alpha,
 }
 
-fn load_table_entries(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+/// Reads a palette file: a Fractint color map if its extension is `.map`, a GIMP gradient if it is
+/// `.ggr`, and otherwise a TOML palette file.
+fn load_palette_file(path: &Path) -> Result<PaletteFile, Box<dyn Error>> {
     let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read palette file {}: {e}", path.display()))?;
-    let file: PaletteFile = toml::from_str(&text).map_err(|e| format!("cannot parse palette file {}: {e}", path.display()))?;
-    file.stops.into_iter().map(|stop| Ok(Entry::new(stop.value, parse_hex_color(&stop.color)?))).collect()
+    let error = |e: String| format!("cannot parse palette file {}: {e}", path.display());
+
+    let stops = match path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("map") => gradient::read_fractint_map(&text).map_err(error)?,
+        Some("ggr") => gradient::read_gimp_gradient(&text).map_err(error)?,
+        _ => return Ok(parse_palette_text(&text, &format!("palette file {}", path.display()))?),
+    };
+    let stops: Vec<StopEntry> = stops
+        .into_iter()
+        .map(|(value, Rgba([r, g, b, a]))| {
+            let color = if a == 255 { format!("#{r:02X}{g:02X}{b:02X}") } else { format!("#{r:02X}{g:02X}{b:02X}{a:02X}") };
+            StopEntry { value, color, alpha: None }
+        })
+        .collect();
+    validate_stops(&stops, |index, _| format!("stop {}", index + 1)).map_err(|e| format!("invalid palette file {}: {e}", path.display()))?;
+    Ok(PaletteFile { stops, color_space: ColorSpace::default(), interpolation: Interpolation::default() })
+}
+
+/// Parses and validates the text of a TOML palette file. `source` describes where the text comes
+/// from, for error messages.
+fn parse_palette_text(text: &str, source: &str) -> Result<PaletteFile, String> {
+    let file: PaletteFileText = toml::from_str(text).map_err(|e| format!("cannot parse {source}: {e}"))?;
+    let line = |index: usize, field: StopField| {
+        let stop = &file.stops[index];
+        let span = match field {
+            StopField::Stop => stop.span(),
+            StopField::Value => stop.get_ref().value.span(),
+            StopField::Color => stop.get_ref().color.span(),
+            StopField::Alpha => stop.get_ref().alpha.as_ref().map_or(stop.span(), toml::Spanned::span),
+        };
+        format!("line {}", text[..span.start].matches('\n').count() + 1)
+    };
+    let stops: Vec<StopEntry> = file
+        .stops
+        .iter()
+        .map(|stop| {
+            let stop = stop.get_ref();
+            StopEntry { value: *stop.value.get_ref(), color: stop.color.get_ref().clone(), alpha: stop.alpha.as_ref().map(|alpha| *alpha.get_ref()) }
+        })
+        .collect();
+    validate_stops(&stops, line).map_err(|e| format!("invalid {source}: {e}"))?;
+    Ok(PaletteFile { stops, color_space: file.color_space, interpolation: file.interpolation })
+}
+
+/// Checks that color stops make up a palette: at least two stops, with valid colors and distinct
+/// values in `0.0..=1.0`. `location` describes where a field of a stop is, by the index of the stop,
+/// for error messages.
+fn validate_stops(stops: &[StopEntry], location: impl Fn(usize, StopField) -> String) -> Result<(), String> {
+    match stops.len() {
+        0 => return Err("there are no stops; a palette needs at least two".to_string()),
+        1 => return Err(format!("{}: this is the only stop; a palette needs at least two", location(0, StopField::Stop))),
+        _ => {}
+    }
+    for (index, stop) in stops.iter().enumerate() {
+        if stop.value.is_nan() {
+            return Err(format!("{}: stop value must be a number, got NaN", location(index, StopField::Value)));
+        }
+        if !(0.0..=1.0).contains(&stop.value) {
+            return Err(format!("{}: stop value must be between 0.0 and 1.0, got {}", location(index, StopField::Value), stop.value));
+        }
+        parse_color(&stop.color).map_err(|e| format!("{}: {e}", location(index, StopField::Color)))?;
+        if let Some(alpha) = stop.alpha && !(0.0..=1.0).contains(&alpha) {
+            return Err(format!("{}: stop alpha must be between 0.0 and 1.0, got {alpha}", location(index, StopField::Alpha)));
+        }
+    }
+
+    let mut order: Vec<usize> = (0..stops.len()).collect();
+    order.sort_by(|&first, &second| stops[first].value.total_cmp(&stops[second].value).then(first.cmp(&second)));
+    for pair in order.windows(2) {
+        if stops[pair[0]].value == stops[pair[1]].value {
+            return Err(format!("{}: stop value {} is the same as the value of the stop at {}", location(pair[1], StopField::Value), stops[pair[1]].value, location(pair[0], StopField::Value)));
+        }
+    }
+    Ok(())
 }
 
-fn parse_hex_color(color: &str) -> Result<Rgb<u8>, Box<dyn Error>> {
+/// Parses a color: a CSS color name, or hexadecimal `#RGB`, `#RGBA`, `#RRGGBB` or `#RRGGBBAA`.
+/// Without an alpha component, the color is opaque.
+fn parse_color(color: &str) -> Result<Rgba<u8>, String> {
+    if !color.starts_with('#') && let Some([r, g, b]) = color_names::named_color(color) {
+        return Ok(Rgba([r, g, b, 255]));
+    }
+
     let hex = color.strip_prefix('#').unwrap_or(color);
-    let invalid = || format!("invalid color '{color}', expected #RRGGBB");
-    if hex.len() != 6 {
-        return Err(invalid().into());
+    let invalid = || format!("invalid color '{color}', expected #RGB, #RGBA, #RRGGBB, #RRGGBBAA or a CSS color name");
+    if !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let digits = match hex.len() {
+        3 | 4 => 1,
+        6 | 8 => 2,
+        _ => return Err(invalid()),
+    };
+    let mut components = [255; 4];
+    for (index, component) in components.iter_mut().take(hex.len() / digits).enumerate() {
+        let value = u8::from_str_radix(&hex[index * digits..(index + 1) * digits], 16).map_err(|_| invalid())?;
+        // A single digit is repeated: #F80 is #FF8800.
+        *component = if digits == 1 { value * 17 } else { value };
     }
-    let component = |range: std::ops::Range<usize>| u8::from_str_radix(&hex[range], 16).map_err(|_| invalid());
-    Ok(Rgb([component(0..2)?, component(2..4)?, component(4..6)?]))
+    Ok(Rgba(components))
+}
+
+/// Returns the palette entry for a stop. Its opacity is the alpha of the stop if it has one, and
+/// otherwise the alpha of its color.
+fn stop_entry(stop: &StopEntry) -> Result<Entry, String> {
+    let Rgba([r, g, b, a]) = parse_color(&stop.color)?;
+    let alpha = stop.alpha.unwrap_or(a as f64 / 255.0);
+    Ok(Entry::new(stop.value, Rgb([r, g, b])).with_alpha(alpha as f32))
 }
 
 // ===== Rendering =============================================================================================================================================
 
-fn render_with_sampler<R, RR, F, M>(sampler: SamplerKind, samples: u32, renderer: &R, filter: &F, value_to_color: &M, width: u32, height: u32) -> RgbImage
+/// The sampler selected on the command line, with its validated sample counts.
+struct SamplerConfig {
+    kind: SamplerKind,
+    samples: u32,
+    strata: (u32, u32),
+}
+
+/// Renders with the given filter, or with a lookup table version of it when a table is requested
+/// and the filter is separable.
+fn render_with_filter<F, R, RR, M>(filter: &F, table: FilterTable, sampler: &SamplerConfig, renderer: &R, value_to_color: &M, output: &Output) -> Result<(), Box<dyn Error>>
 where
+    F: Filter + Sync,
     R: Renderer<Output=RR> + Sync,
-    RR: RendererOutput,
+    RR: RendererOutput + Into<f64>,
+    M: Fn(Option<f64>) -> Rgba<f32> + Sync,
+{
+    if table != FilterTable::Off && filter.separable() {
+        let filter = TableFilter::new(filter, FILTER_TABLE_SIZE, table == FilterTable::Linear);
+        render_with_sampler(sampler, renderer, &filter, value_to_color, output)
+    } else {
+        render_with_sampler(sampler, renderer, filter, value_to_color, output)
+    }
+}
+
+fn render_with_sampler<R, RR, F, M>(sampler: &SamplerConfig, renderer: &R, filter: &F, value_to_color: &M, output: &Output) -> Result<(), Box<dyn Error>>
+where
+    R: Renderer<Output=RR> + Sync,
+    RR: RendererOutput + Into<f64>,
     F: Filter + Sync,
-    M: Fn(Option<RR>) -> Rgb<u8> + Sync,
+    M: Fn(Option<f64>) -> Rgba<f32> + Sync,
 {
-    match sampler {
-        SamplerKind::Simple => render_image(&|x, y| SimpleSampler::new(x, y), renderer, filter, value_to_color, width, height),
-        SamplerKind::Stratified => render_image(&|x, y| StratifiedSampler::new(x, y, samples, true), renderer, filter, value_to_color, width, height),
+    let (samples, (strata_x, strata_y)) = (sampler.samples, sampler.strata);
+    match sampler.kind {
+        SamplerKind::Simple => render_to_output(&|x, y| SimpleSampler::new(x, y), renderer, filter, value_to_color, output),
+        SamplerKind::Stratified => render_to_output(&|x, y| StratifiedSampler::new(x, y, strata_x, strata_y, true), renderer, filter, value_to_color, output),
+        SamplerKind::MultiJittered => render_to_output(&|x, y| MultiJitteredSampler::new(x, y, samples), renderer, filter, value_to_color, output),
+    }
+}
+
+/// Where the rendered image goes, and whether it is rendered in one piece or in tiles.
+struct Output<'a> {
+    path: PathBuf,
+    format: Format,
+    bit_depth: BitDepth,
+    width: u32,
+    height: u32,
+    tile_size: Option<u32>,
+    value_files: Vec<PathBuf>,
+    view: View,
+    fingerprint: u64,
+    text: Vec<TextChunk>,
+    /// The transfer function that the values go through before they are mapped to colors.
+    transfer: Transfer,
+    /// The opacity of the interior of the set, if the image has an alpha channel.
+    alpha: Option<f32>,
+    /// Where the image goes instead of the output file.
+    sink: Option<Sink<'a>>,
+}
+
+/// Receives a rendered image instead of the output file.
+#[derive(Clone, Copy)]
+enum Sink<'a> {
+    /// The image is written to a video stream as its next frame.
+    Video(&'a RefCell<Y4mWriter>),
+    /// The values are kept, uncolored, to be colored later.
+    Values(&'a RefCell<Option<ValueFile>>),
+}
+
+fn render_to_output<SF, S, R, RR, F, M>(sampler_factory: &SF, renderer: &R, filter: &F, value_to_color: &M, output: &Output) -> Result<(), Box<dyn Error>>
+where
+    SF: Fn(u32, u32) -> S + Sync,
+    S: Sampler,
+    R: Renderer<Output=RR> + Sync,
+    RR: RendererOutput + Into<f64>,
+    F: Filter + Sync,
+    M: Fn(Option<f64>) -> Rgba<f32> + Sync,
+{
+    match output.tile_size {
+        Some(tile_size) => render_tiled(sampler_factory, renderer, filter, value_to_color, output, tile_size),
+        None => {
+            let (values, coverage) = render_values(sampler_factory, renderer, filter, output.width, output.height);
+            let values: Vec<Option<f64>> = values.into_iter().map(|value| value.map(Into::into)).collect();
+            let keep_values = matches!(output.sink, Some(Sink::Values(_)));
+            if !output.value_files.is_empty() || keep_values {
+                let file = match output.alpha {
+                    Some(_) => {
+                        let raw = values.iter().zip(&coverage).flat_map(|(value, &coverage)| [value.unwrap_or(f64::NAN), coverage as f64]).collect();
+                        ValueFile::new(output.width, output.height, 2, output.view, output.fingerprint, raw)
+                    }
+                    None => {
+                        let raw = values.iter().map(|value| value.unwrap_or(f64::NAN)).collect();
+                        ValueFile::new(output.width, output.height, 1, output.view, output.fingerprint, raw)
+                    }
+                };
+                for path in &output.value_files {
+                    file.write(path)?;
+                }
+                if let Some(Sink::Values(kept)) = output.sink {
+                    *kept.borrow_mut() = Some(file);
+                    return Ok(());
+                }
+            }
+            let values = output.transfer.apply_all(&values);
+            if let Some(interior_alpha) = output.alpha {
+                let image = colorize_rgba(&values, &coverage, output.width, output.height, value_to_color, interior_alpha);
+                return save_rgba_image(&image, &output.path, output.format, output.bit_depth, &output.text);
+            }
+            let image = colorize(&values, output.width, output.height, value_to_color);
+            match output.sink {
+                Some(Sink::Video(video)) => video.borrow_mut().write_frame(&image),
+                _ => save_image(&image, &output.path, output.format, output.bit_depth, &output.text),
+            }
+        }
     }
 }
 
@@ -239,68 +1378,290 @@ struct StoredSample<R> {
     value: R,
 }
 
-fn render_image<SF, S, R, RR, F, M>(sampler_factory: &SF, renderer: &R, filter: &F, value_to_color: &M, width: u32, height: u32) -> RgbImage
+/// The samples rendered for a rectangular block of pixels, grouped per pixel in row-major order.
+struct SampleBlock<R> {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    samples: Vec<Vec<StoredSample<R>>>,
+}
+
+impl<R: RendererOutput> SampleBlock<R> {
+    /// Reconstructs the value of pixel `(x, y)` by gathering every sample in the block within the
+    /// filter's radius. Because the filter can reach beyond the pixel, samples generated in
+    /// neighboring pixels contribute too; the block must cover those pixels for the result to match
+    /// an untiled render. That also relies on the samplers, which give a pixel the same samples in
+    /// every block that covers it.
+    fn reconstruct<F: Filter>(&self, filter: &F, x: usize, y: usize) -> Option<R> {
+        let (radius_x, radius_y) = filter.radius();
+        let center_x = x as f64 + 0.5;
+        let center_y = y as f64 + 0.5;
+
+        let x_lo = ((center_x - radius_x).floor().max(0.0) as usize).max(self.x);
+        let x_hi = ((center_x + radius_x).floor().max(0.0) as usize).min(self.x + self.width - 1);
+        let y_lo = ((center_y - radius_y).floor().max(0.0) as usize).max(self.y);
+        let y_hi = ((center_y + radius_y).floor().max(0.0) as usize).min(self.y + self.height - 1);
+
+        let mut reconstructor = Reconstructor::new(filter);
+        for sy in y_lo..=y_hi {
+            for sx in x_lo..=x_hi {
+                for stored in &self.samples[(sy - self.y) * self.width + (sx - self.x)] {
+                    let dx = sx as f64 + stored.offset_x as f64 - center_x;
+                    let dy = sy as f64 + stored.offset_y as f64 - center_y;
+                    reconstructor.accumulate(stored.value, dx, dy);
+                }
+            }
+        }
+        reconstructor.value()
+    }
+}
+
+/// Generates and renders every sample of pixel `(x, y)`. Only samples that produced a value are
+/// kept (samples inside the set produce None), so interior regions store nothing. Also returns the
+/// coverage of the pixel: the fraction of its samples that produced a value.
+fn render_pixel<SF, S, R, RR>(sampler_factory: &SF, renderer: &R, x: u32, y: u32) -> (Vec<StoredSample<RR>>, f32)
+where
+    SF: Fn(u32, u32) -> S,
+    S: Sampler,
+    R: Renderer<Output=RR>,
+{
+    let mut pixel_samples = Vec::new();
+    let mut count = 0;
+    for sample in sampler_factory(x, y) {
+        count += 1;
+        if let Some(value) = renderer.render(&sample) {
+            let (offset_x, offset_y) = sample.offset();
+            pixel_samples.push(StoredSample { offset_x: offset_x as f32, offset_y: offset_y as f32, value });
+        }
+    }
+    let coverage = if count > 0 { pixel_samples.len() as f32 / count as f32 } else { 0.0 };
+    (pixel_samples, coverage)
+}
+
+/// Renders the image and returns the reconstructed value and the coverage of each pixel, in
+/// row-major order.
+fn render_values<SF, S, R, RR, F>(sampler_factory: &SF, renderer: &R, filter: &F, width: u32, height: u32) -> (Vec<Option<RR>>, Vec<f32>)
 where
     SF: Fn(u32, u32) -> S + Sync,
     S: Sampler,
     R: Renderer<Output=RR> + Sync,
     RR: RendererOutput,
     F: Filter + Sync,
-    M: Fn(Option<RR>) -> Rgb<u8> + Sync,
 {
     let width = width as usize;
     let height = height as usize;
 
-    // Pass 1: generate and render every sample, grouped per pixel. Only samples that produced a
-    // value are kept (samples inside the set produce None), so interior regions store nothing.
+    // Pass 1: generate and render every sample, grouped per pixel.
     let start_time_pass_1 = Instant::now();
-    let samples: Vec<Vec<StoredSample<RR>>> = (0..width * height)
+    let (samples, coverage) = (0..width * height)
         .into_par_iter()
-        .map(|index| {
-            let x = (index % width) as u32;
-            let y = (index / width) as u32;
-
-            let mut pixel_samples = Vec::new();
-            for sample in sampler_factory(x, y) {
-                if let Some(value) = renderer.render(&sample) {
-                    let (offset_x, offset_y) = sample.offset();
-                    pixel_samples.push(StoredSample { offset_x: offset_x as f32, offset_y: offset_y as f32, value });
-                }
-            }
-            pixel_samples
-        })
-        .collect();
-    println!("Pass 1 (sampling and rendering): {} ms", Instant::now().duration_since(start_time_pass_1).as_millis());
+        .map(|index| render_pixel(sampler_factory, renderer, (index % width) as u32, (index / width) as u32))
+        .unzip();
+    let block = SampleBlock { x: 0, y: 0, width, height, samples };
+    eprintln!("Pass 1 (sampling and rendering): {} ms", Instant::now().duration_since(start_time_pass_1).as_millis());
 
-    // Pass 2: reconstruct each pixel by gathering every sample within the filter's radius. Because
-    // the filter can reach beyond the pixel, samples generated in neighboring pixels contribute
-    // too. Each output pixel is written by exactly one task, so no synchronization is needed.
+    // Pass 2: reconstruct each pixel from the samples around it. Each output pixel is written by
+    // exactly one task, so no synchronization is needed.
     let start_time_pass_2 = Instant::now();
+    let values = (0..width * height).into_par_iter().map(|index| block.reconstruct(filter, index % width, index / width)).collect();
+    eprintln!("Pass 2 (reconstruction): {} ms", Instant::now().duration_since(start_time_pass_2).as_millis());
+    eprintln!("Total time: {} ms", Instant::now().duration_since(start_time_pass_1).as_millis());
+
+    (values, coverage)
+}
+
+/// Maps reconstructed values, in row-major order, to the colors of an image.
+fn colorize<V, M>(values: &[V], width: u32, height: u32, value_to_color: &M) -> Rgb32FImage
+where
+    V: Copy + Sync,
+    M: Fn(V) -> Rgba<f32> + Sync,
+{
+    Rgb32FImage::from_par_fn(width, height, |x, y| opaque(value_to_color(values[y as usize * width as usize + x as usize])))
+}
+
+/// Maps reconstructed values and coverage, in row-major order, to the colors and opacity of an
+/// image. A pixel has the color of its value, as in [`colorize`]; the part of it that is covered
+/// has the opacity of that color, and the rest, which is inside the set, has opacity
+/// `interior_alpha`. With an opaque palette and `interior_alpha` 1, the image is that of [`colorize`].
+fn colorize_rgba<M>(values: &[Option<f64>], coverage: &[f32], width: u32, height: u32, value_to_color: &M, interior_alpha: f32) -> Rgba32FImage
+where
+    M: Fn(Option<f64>) -> Rgba<f32> + Sync,
+{
+    Rgba32FImage::from_par_fn(width, height, |x, y| {
+        let index = y as usize * width as usize + x as usize;
+        let value = values[index];
+        let [r, g, b, a] = value_to_color(value).0;
+
+        let covered = if value.is_some() { coverage[index] } else { 0.0 };
+        Rgba([r, g, b, a * covered + interior_alpha * (1.0 - covered)])
+    })
+}
+
+/// Drops the opacity of a color.
+fn opaque(Rgba([r, g, b, _]): Rgba<f32>) -> Rgb<f32> {
+    Rgb([r, g, b])
+}
+
+/// Renders the image in tiles of `tile_size` by `tile_size` pixels and writes it to the output PNG
+/// file, one row of tiles at a time.
+///
+/// Each tile renders its own samples, plus an apron around it as wide as the filter's radius, and
+/// then reconstructs its pixels; the tiles in a row are processed in parallel. Samples in the apron
+/// are rendered by both neighboring tiles, which costs some extra time, but peak memory is bounded
+/// by the tile size and the image width rather than the image size.
+fn render_tiled<SF, S, R, RR, F, M>(sampler_factory: &SF, renderer: &R, filter: &F, value_to_color: &M, output: &Output, tile_size: u32) -> Result<(), Box<dyn Error>>
+where
+    SF: Fn(u32, u32) -> S + Sync,
+    S: Sampler,
+    R: Renderer<Output=RR> + Sync,
+    RR: RendererOutput + Into<f64>,
+    F: Filter + Sync,
+    M: Fn(Option<f64>) -> Rgba<f32> + Sync,
+{
+    let start_time = Instant::now();
+
+    let mut writer = PngRowWriter::create(&output.path, output.width, output.height, output.bit_depth, &output.text)?;
+
+    let (width, height, tile_size) = (output.width as usize, output.height as usize, tile_size as usize);
+    let transfer = output.transfer;
     let (radius_x, radius_y) = filter.radius();
-    let image = RgbImage::from_par_fn(width as u32, height as u32, |x, y| {
-        let center_x = x as f64 + 0.5;
-        let center_y = y as f64 + 0.5;
+    let (apron_x, apron_y) = (radius_x.ceil() as usize, radius_y.ceil() as usize);
+    let tile_xs: Vec<usize> = (0..width).step_by(tile_size).collect();
+    let tile_widths: Vec<usize> = tile_xs.iter().map(|&tile_x| tile_size.min(width - tile_x)).collect();
 
-        let x_lo = (center_x - radius_x).floor().max(0.0) as usize;
-        let x_hi = ((center_x + radius_x).floor().max(0.0) as usize).min(width - 1);
-        let y_lo = (center_y - radius_y).floor().max(0.0) as usize;
-        let y_hi = ((center_y + radius_y).floor().max(0.0) as usize).min(height - 1);
+    for tile_y in (0..height).step_by(tile_size) {
+        let tile_height = tile_size.min(height - tile_y);
 
-        let mut reconstructor = Reconstructor::new(filter);
-        for sy in y_lo..=y_hi {
-            for sx in x_lo..=x_hi {
-                for stored in &samples[sy * width + sx] {
-                    let dx = sx as f64 + stored.offset_x as f64 - center_x;
-                    let dy = sy as f64 + stored.offset_y as f64 - center_y;
-                    reconstructor.accumulate(stored.value, dx, dy);
-                }
-            }
-        }
+        let tiles: Vec<Vec<Rgb<f32>>> = tile_xs
+            .par_iter()
+            .zip(&tile_widths)
+            .map(|(&tile_x, &tile_width)| {
+                let x = tile_x.saturating_sub(apron_x);
+                let y = tile_y.saturating_sub(apron_y);
+                let block_width = (tile_x + tile_width + apron_x).min(width) - x;
+                let block_height = (tile_y + tile_height + apron_y).min(height) - y;
+                let samples = (0..block_width * block_height)
+                    .map(|index| render_pixel(sampler_factory, renderer, (x + index % block_width) as u32, (y + index / block_width) as u32).0)
+                    .collect();
+                let block = SampleBlock { x, y, width: block_width, height: block_height, samples };
+
+                (0..tile_width * tile_height)
+                    .map(|index| block.reconstruct(filter, tile_x + index % tile_width, tile_y + index / tile_width))
+                    .map(|value| opaque(value_to_color(value.map(|value| transfer.apply(value.into())))))
+                    .collect()
+            })
+            .collect();
 
-        value_to_color(reconstructor.value())
-    });
-    println!("Pass 2 (reconstruction): {} ms", Instant::now().duration_since(start_time_pass_2).as_millis());
-    println!("Total time: {} ms", Instant::now().duration_since(start_time_pass_1).as_millis());
+        writer.write_tiles(&tiles, &tile_widths)?;
+    }
 
-    image
+    writer.finish()?;
+    eprintln!("Total time: {} ms", Instant::now().duration_since(start_time).as_millis());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a path for a temporary file or directory, unique to the test.
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mandelbrot-{}-{name}", std::process::id()))
+    }
+
+    /// Parses a command line, and resolves the arguments as `main` does.
+    fn parse_args(arguments: &[&str]) -> Args {
+        let matches = Cli::command().try_get_matches_from(std::iter::once("mandelbrot").chain(arguments.iter().copied())).unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        resolve_args(&cli.args, &matches).unwrap()
+    }
+
+    #[test]
+    fn dumped_scenes_load_again_from_another_directory() {
+        let dir = temporary_path("dump-scene");
+        std::fs::create_dir_all(dir.join("scenes")).unwrap();
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        std::fs::write(dir.join("palette.toml"), "[[stops]]\nvalue = 0.0\ncolor = \"#000\"\n\n[[stops]]\nvalue = 1.0\ncolor = \"#fff\"\n").unwrap();
+        std::fs::write(dir.join("scenes/scene.toml"), "palette = \"table\"\npalette_file = \"../palette.toml\"\nmax_iterations = 123\n").unwrap();
+
+        let scene = dir.join("scenes/scene.toml");
+        let dumped = dir.join("out/scene.toml");
+        dump_scene(&parse_args(&["--scene", scene.to_str().unwrap()]), &dumped).unwrap();
+        let args = parse_args(&["--scene", dumped.to_str().unwrap()]);
+        let table = table_palette(&args.palette);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(args.max_iterations, 123);
+        assert_eq!(table.unwrap().stops.len(), 2);
+    }
+
+    /// Returns stops with the given values, all black.
+    fn black_stops(values: &[f64]) -> Vec<StopEntry> {
+        values.iter().map(|&value| StopEntry { value, color: "#000".to_string(), alpha: None }).collect()
+    }
+
+    /// Returns the error for the text of an invalid palette file.
+    fn palette_text_error(text: &str) -> String {
+        parse_palette_text(text, "test").err().expect("invalid palette text")
+    }
+
+    #[test]
+    fn colors_are_parsed_in_every_form() {
+        assert_eq!(parse_color("#F80"), Ok(Rgba([255, 136, 0, 255])));
+        assert_eq!(parse_color("#F808"), Ok(Rgba([255, 136, 0, 136])));
+        assert_eq!(parse_color("#FF8800"), Ok(Rgba([255, 136, 0, 255])));
+        assert_eq!(parse_color("#ff880080"), Ok(Rgba([255, 136, 0, 128])));
+        assert_eq!(parse_color("rebeccapurple"), Ok(Rgba([102, 51, 153, 255])));
+    }
+
+    #[test]
+    fn invalid_colors_are_rejected() {
+        assert_eq!(parse_color("notacolor").unwrap_err(), "invalid color 'notacolor', expected #RGB, #RGBA, #RRGGBB, #RRGGBBAA or a CSS color name");
+        assert!(parse_color("#FF880G").is_err());
+        assert!(parse_color("#FF88").is_ok() && parse_color("#FF88800").is_err());
+        assert!(parse_color("#rebeccapurple").is_err());
+    }
+
+    #[test]
+    fn stops_must_make_up_a_palette() {
+        let location = |index: usize, _| format!("stop {}", index + 1);
+        assert_eq!(validate_stops(&black_stops(&[0.5]), location).unwrap_err(), "stop 1: this is the only stop; a palette needs at least two");
+        assert_eq!(validate_stops(&black_stops(&[1.0, 0.25, 0.0]), location), Ok(()));
+        assert_eq!(validate_stops(&black_stops(&[0.0, 1.5]), location).unwrap_err(), "stop 2: stop value must be between 0.0 and 1.0, got 1.5");
+        assert_eq!(
+            validate_stops(&black_stops(&[0.5, 1.0, 0.0, 0.5]), location).unwrap_err(),
+            "stop 4: stop value 0.5 is the same as the value of the stop at stop 1"
+        );
+    }
+
+    #[test]
+    fn palette_text_errors_point_at_the_line_of_the_field() {
+        let text = "[[stops]]\nvalue = 0.0\ncolor = \"#000\"\n\n[[stops]]\nvalue = 1.0\ncolor = \"#00G\"\n";
+        assert!(palette_text_error(text).starts_with("invalid test: line 7: invalid color '#00G'"));
+
+        let text = "[[stops]]\nvalue = 0.5\ncolor = \"#000\"\n\n[[stops]]\ncolor = \"#FFF\"\nalpha = 0.5\nvalue = 0.5\n";
+        assert_eq!(palette_text_error(text), "invalid test: line 8: stop value 0.5 is the same as the value of the stop at line 2");
+
+        let text = "[[stops]]\nvalue = 0.0\ncolor = \"#000\"\nalpha = 2.0\n\n[[stops]]\nvalue = 1.0\ncolor = \"#FFF\"\n";
+        assert_eq!(palette_text_error(text), "invalid test: line 4: stop alpha must be between 0.0 and 1.0, got 2");
+    }
+
+    #[test]
+    fn an_opaque_interior_matches_the_image_without_alpha() {
+        let palette = Grayscale::new(0.0..1.0);
+        let value_to_color = |value| color_or_background(&palette, value);
+        let values = [Some(0.25), None, Some(0.75), Some(0.5)];
+        let coverage = [1.0, 0.0, 0.5, 0.25];
+
+        let opaque = colorize(&values, 2, 2, &value_to_color);
+        let transparent = colorize_rgba(&values, &coverage, 2, 2, &value_to_color, 1.0);
+        for (Rgb([r, g, b]), with_alpha) in opaque.pixels().zip(transparent.pixels()) {
+            assert_eq!(with_alpha.0, [*r, *g, *b, 1.0]);
+        }
+
+        let alphas: Vec<f32> = colorize_rgba(&values, &coverage, 2, 2, &value_to_color, 0.0).pixels().map(|pixel| pixel.0[3]).collect();
+        assert_eq!(alphas, [1.0, 0.0, 0.5, 0.25]);
+    }
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::f32::consts::TAU;
 use std::ops::{Add, Mul};
 
 use num_traits::Num;
@@ -24,3 +25,190 @@ where
 {
     left * (T::one() - value) + right * value
 }
+
+/// Converts an sRGB encoded color component in `0.0..=1.0` to linear light.
+#[inline]
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Converts a color component in linear light to sRGB encoding.
+#[inline]
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Converts an sRGB encoded color to OKLab coordinates `[L, a, b]`.
+#[allow(clippy::excessive_precision)]
+pub fn srgb_to_oklab(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(srgb_to_linear);
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// Converts OKLab coordinates `[L, a, b]` to an sRGB encoded color. Colors outside of the sRGB gamut
+/// are clipped.
+#[allow(clippy::excessive_precision)]
+pub fn oklab_to_srgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+    .map(|c| linear_to_srgb(c.clamp(0.0, 1.0)))
+}
+
+/// Converts OKLab coordinates `[L, a, b]` to OKLCh coordinates `[L, C, h]`, with the hue in turns.
+pub fn oklab_to_oklch([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    [lightness, a.hypot(b), (b.atan2(a) / TAU).rem_euclid(1.0)]
+}
+
+/// Converts OKLCh coordinates `[L, C, h]`, with the hue in turns, to OKLab coordinates `[L, a, b]`.
+pub fn oklch_to_oklab([lightness, chroma, hue]: [f32; 3]) -> [f32; 3] {
+    let (sin, cos) = (hue * TAU).sin_cos();
+    [lightness, chroma * cos, chroma * sin]
+}
+
+/// Converts an sRGB encoded color to HSL coordinates `[h, s, l]`, with the hue in turns.
+pub fn srgb_to_hsl(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta <= 0.0 {
+        return [0.0, 0.0, lightness];
+    }
+
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    [hue / 6.0, saturation, lightness]
+}
+
+/// Converts HSL coordinates `[h, s, l]`, with the hue in turns, to an sRGB encoded color.
+pub fn hsl_to_srgb([hue, saturation, lightness]: [f32; 3]) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let component = |n: f32| {
+        let k = (n + hue * 12.0).rem_euclid(12.0);
+        lightness - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    [component(0.0), component(8.0), component(4.0)]
+}
+
+/// Converts an sRGB encoded color to HSV coordinates `[h, s, v]`, with the hue in turns.
+pub fn srgb_to_hsv(color: [f32; 3]) -> [f32; 3] {
+    let [hue, saturation, lightness] = srgb_to_hsl(color);
+    let value = lightness + saturation * lightness.min(1.0 - lightness);
+    let saturation = if value > 0.0 { 2.0 * (1.0 - lightness / value) } else { 0.0 };
+    [hue, saturation, value]
+}
+
+/// Converts HSV coordinates `[h, s, v]`, with the hue in turns, to an sRGB encoded color.
+pub fn hsv_to_srgb([hue, saturation, value]: [f32; 3]) -> [f32; 3] {
+    let component = |n: f32| {
+        let k = (n + hue * 6.0).rem_euclid(6.0);
+        value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    [component(5.0), component(3.0), component(1.0)]
+}
+
+/// Interpolates between two hues in turns along the shorter way around the color wheel.
+#[inline]
+pub fn interpolate_hue(value: f32, left: f32, right: f32) -> f32 {
+    let difference = right - left;
+    (left + (difference - difference.round()) * value).rem_euclid(1.0)
+}
+
+/// A cubic Hermite spline through the points `(times[i], values[i])`, with increasing times.
+pub struct Spline {
+    times: Vec<f64>,
+    values: Vec<f64>,
+    tangents: Vec<f64>,
+}
+
+impl Spline {
+    /// Creates a spline with Catmull-Rom tangents: the slope between the neighboring points, or the
+    /// slope to the only neighbor at the ends.
+    pub fn catmull_rom(times: Vec<f64>, values: Vec<f64>) -> Spline {
+        let last = times.len() - 1;
+        let tangents = (0..times.len())
+            .map(|i| {
+                let (before, after) = (i.saturating_sub(1), (i + 1).min(last));
+                (values[after] - values[before]) / (times[after] - times[before])
+            })
+            .collect();
+
+        Spline { times, values, tangents }
+    }
+
+    /// Creates a monotone spline with the tangents of Fritsch and Carlson: between two points, the
+    /// spline stays within their values, so it never overshoots.
+    pub fn monotone(times: Vec<f64>, values: Vec<f64>) -> Spline {
+        let last = times.len() - 1;
+        let slopes: Vec<f64> = (0..last).map(|i| (values[i + 1] - values[i]) / (times[i + 1] - times[i])).collect();
+
+        let mut tangents: Vec<f64> = (0..times.len())
+            .map(|i| match (i.checked_sub(1).map(|before| slopes[before]), slopes.get(i).copied()) {
+                (Some(before), Some(after)) if before * after > 0.0 => (before + after) / 2.0,
+                (Some(_), Some(_)) => 0.0,
+                (Some(slope), None) | (None, Some(slope)) => slope,
+                (None, None) => 0.0,
+            })
+            .collect();
+
+        // Limit the tangents of each segment, so that the segment is monotone.
+        for (i, &slope) in slopes.iter().enumerate() {
+            if slope == 0.0 {
+                tangents[i] = 0.0;
+                tangents[i + 1] = 0.0;
+            } else {
+                let (alpha, beta) = (tangents[i] / slope, tangents[i + 1] / slope);
+                let length = alpha.hypot(beta);
+                if length > 3.0 {
+                    tangents[i] = 3.0 / length * alpha * slope;
+                    tangents[i + 1] = 3.0 / length * beta * slope;
+                }
+            }
+        }
+
+        Spline { times, values, tangents }
+    }
+
+    /// Evaluates the spline at `time`, which is clamped to the times of the first and last points.
+    pub fn evaluate(&self, time: f64) -> f64 {
+        let last = self.times.len() - 1;
+        if last == 0 {
+            return self.values[0];
+        }
+        let index = self.times[1..last].partition_point(|&t| t <= time);
+
+        let dt = self.times[index + 1] - self.times[index];
+        let u = ((time - self.times[index]) / dt).clamp(0.0, 1.0);
+        let (u2, u3) = (u * u, u * u * u);
+
+        let h00 = 2.0 * u3 - 3.0 * u2 + 1.0;
+        let h10 = u3 - 2.0 * u2 + u;
+        let h01 = -2.0 * u3 + 3.0 * u2;
+        let h11 = u3 - u2;
+
+        h00 * self.values[index] + h10 * dt * self.tangents[index] + h01 * self.values[index + 1] + h11 * dt * self.tangents[index + 1]
+    }
+}
@@ -0,0 +1,425 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use image::{ImageBuffer, ImageFormat, Rgb, Rgb32FImage, RgbImage, Rgba, Rgba32FImage};
+use serde::{Deserialize, Serialize};
+
+use crate::math::srgb_to_linear;
+
+/// File format of the output image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// PNG, with 8 or 16 bits per channel.
+    Png,
+    /// OpenEXR, with 32-bit floating point channels in linear light.
+    Exr,
+    /// Radiance HDR (RGBE), floating point in linear light.
+    Hdr,
+    /// GIF, with the colors reduced to a palette of 256.
+    Gif,
+}
+
+/// Number of bits per color channel in the output image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+pub enum BitDepth {
+    /// 8-bit integer channels (PNG and GIF).
+    #[value(name = "8")]
+    #[serde(rename = "8")]
+    Eight,
+    /// 16-bit integer channels (PNG only).
+    #[value(name = "16")]
+    #[serde(rename = "16")]
+    Sixteen,
+    /// 32-bit floating point channels (EXR and HDR only).
+    #[value(name = "32")]
+    #[serde(rename = "32")]
+    ThirtyTwo,
+}
+
+/// A text chunk to store in PNG output, as a keyword and its text.
+pub type TextChunk = (String, String);
+
+/// Writes PNG rows one at a time, for images that are produced incrementally and never held in
+/// memory as a whole.
+pub struct PngRowWriter {
+    stream: png::StreamWriter<'static, BufWriter<File>>,
+    bit_depth: BitDepth,
+    row: Vec<u8>,
+}
+
+/// Writes frames as an uncompressed YUV4MPEG2 (Y4M) stream, which video encoders such as ffmpeg
+/// read directly. Frames are converted to Y'CbCr with the BT.709 matrix, in limited range, with
+/// 4:2:0 chroma subsampling.
+pub struct Y4mWriter {
+    writer: Box<dyn Write>,
+    width: u32,
+    height: u32,
+}
+
+/// Writes the frames of an animation, one at a time, as an animated PNG (APNG) or GIF that loops
+/// forever.
+pub struct AnimationWriter {
+    encoder: AnimationEncoder,
+    bit_depth: BitDepth,
+}
+
+enum AnimationEncoder {
+    Apng(png::Writer<BufWriter<File>>),
+    /// The GIF encoder, and the delay between frames in hundredths of a second.
+    Gif(gif::Encoder<BufWriter<File>>, u16),
+}
+
+// ===== Format ================================================================================================================================================
+
+impl Format {
+    /// Determines the format from the extension of `path`, if it is one of the supported formats.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Format::Png),
+            "exr" => Some(Format::Exr),
+            "hdr" => Some(Format::Hdr),
+            "gif" => Some(Format::Gif),
+            _ => None,
+        }
+    }
+
+    /// The bit depth that is used when none is requested.
+    pub fn default_bit_depth(self) -> BitDepth {
+        match self {
+            Format::Png | Format::Gif => BitDepth::Eight,
+            Format::Exr | Format::Hdr => BitDepth::ThirtyTwo,
+        }
+    }
+
+    /// Whether the format can be written at the given bit depth.
+    pub fn supports(self, bit_depth: BitDepth) -> bool {
+        match self {
+            Format::Png => bit_depth != BitDepth::ThirtyTwo,
+            Format::Gif => bit_depth == BitDepth::Eight,
+            Format::Exr | Format::Hdr => bit_depth == BitDepth::ThirtyTwo,
+        }
+    }
+}
+
+// ===== Image output ==========================================================================================================================================
+
+/// Saves an image with sRGB encoded floating point colors in `format`. PNG output is quantized to
+/// `bit_depth` bits per channel and gets the given text chunks; the floating point formats are
+/// converted to linear light, as is conventional for them, and carry no text, and neither does GIF.
+pub fn save_image(image: &Rgb32FImage, path: &Path, format: Format, bit_depth: BitDepth, text: &[TextChunk]) -> Result<(), Box<dyn Error>> {
+    let error = |e| format!("cannot write image {}: {e}", path.display());
+    match (format, bit_depth) {
+        (Format::Png, BitDepth::Eight | BitDepth::Sixteen) => {
+            let mut writer = png_writer(path, image.width(), image.height(), png::ColorType::Rgb, bit_depth, text)?;
+            let data: Vec<u8> = match bit_depth {
+                BitDepth::Sixteen => image.as_raw().iter().flat_map(|&c| to_u16(c).to_be_bytes()).collect(),
+                _ => image.as_raw().iter().map(|&c| to_u8(c)).collect(),
+            };
+            writer.write_image_data(&data).map_err(|e| error(e.to_string()))?;
+            writer.finish().map_err(|e| error(e.to_string()))?;
+        }
+        (Format::Exr | Format::Hdr, BitDepth::ThirtyTwo) => {
+            let image: Rgb32FImage = ImageBuffer::from_fn(image.width(), image.height(), |x, y| Rgb(image.get_pixel(x, y).0.map(srgb_to_linear)));
+            let image_format = if format == Format::Exr { ImageFormat::OpenExr } else { ImageFormat::Hdr };
+            image.save_with_format(path, image_format).map_err(|e| error(e.to_string()))?;
+        }
+        (Format::Gif, BitDepth::Eight) => {
+            let image: RgbImage = ImageBuffer::from_fn(image.width(), image.height(), |x, y| Rgb(image.get_pixel(x, y).0.map(to_u8)));
+            image.save_with_format(path, ImageFormat::Gif).map_err(|e| error(e.to_string()))?;
+        }
+        _ => return Err("unsupported combination of format and bit depth".into()),
+    }
+    Ok(())
+}
+
+/// Saves an image with sRGB encoded floating point colors, as [`save_image`] does, and straight (not
+/// premultiplied) alpha. Only PNG and EXR have an alpha channel: PNG is written with straight alpha,
+/// as the format requires, and EXR with premultiplied linear colors, as OpenEXR expects.
+pub fn save_rgba_image(image: &Rgba32FImage, path: &Path, format: Format, bit_depth: BitDepth, text: &[TextChunk]) -> Result<(), Box<dyn Error>> {
+    let error = |e| format!("cannot write image {}: {e}", path.display());
+    match (format, bit_depth) {
+        (Format::Png, BitDepth::Eight | BitDepth::Sixteen) => {
+            let mut writer = png_writer(path, image.width(), image.height(), png::ColorType::Rgba, bit_depth, text)?;
+            let data: Vec<u8> = match bit_depth {
+                BitDepth::Sixteen => image.as_raw().iter().flat_map(|&c| to_u16(c).to_be_bytes()).collect(),
+                _ => image.as_raw().iter().map(|&c| to_u8(c)).collect(),
+            };
+            writer.write_image_data(&data).map_err(|e| error(e.to_string()))?;
+            writer.finish().map_err(|e| error(e.to_string()))?;
+        }
+        (Format::Exr, BitDepth::ThirtyTwo) => {
+            let image: Rgba32FImage = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                let [r, g, b, a] = image.get_pixel(x, y).0;
+                Rgba([srgb_to_linear(r) * a, srgb_to_linear(g) * a, srgb_to_linear(b) * a, a])
+            });
+            image.save_with_format(path, ImageFormat::OpenExr).map_err(|e| error(e.to_string()))?;
+        }
+        _ => return Err("an alpha channel is only supported for PNG and EXR output".into()),
+    }
+    Ok(())
+}
+
+/// Creates a PNG file with pixels of the given color type and bit depth, and writes its header and
+/// text chunks.
+fn png_writer(path: &Path, width: u32, height: u32, color: png::ColorType, bit_depth: BitDepth, text: &[TextChunk]) -> Result<png::Writer<BufWriter<File>>, Box<dyn Error>> {
+    Ok(png_encoder(path, width, height, color, bit_depth, text)?.write_header()?)
+}
+
+/// Creates a PNG file, and sets up an encoder for pixels of the given color type and bit depth with
+/// the given text chunks.
+fn png_encoder(path: &Path, width: u32, height: u32, color: png::ColorType, bit_depth: BitDepth, text: &[TextChunk]) -> Result<png::Encoder<'static, BufWriter<File>>, Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path).map_err(|e| format!("cannot create {}: {e}", path.display()))?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(match bit_depth {
+        BitDepth::Eight => png::BitDepth::Eight,
+        BitDepth::Sixteen => png::BitDepth::Sixteen,
+        BitDepth::ThirtyTwo => return Err("PNG does not support 32-bit channels".into()),
+    });
+    for (keyword, text) in text {
+        encoder.add_itxt_chunk(keyword.clone(), text.clone())?;
+    }
+    Ok(encoder)
+}
+
+/// Reads the text of the text chunk with the given keyword from a PNG file, if it has one.
+pub fn read_png_text(path: &Path, keyword: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let error = |e: &dyn std::fmt::Display| format!("cannot read PNG image {}: {e}", path.display());
+    let file = BufReader::new(File::open(path).map_err(|e| error(&e))?);
+    let reader = png::Decoder::new(file).read_info().map_err(|e| error(&e))?;
+    let info = reader.info();
+
+    if let Some(chunk) = info.utf8_text.iter().find(|chunk| chunk.keyword == keyword) {
+        return Ok(Some(chunk.get_text().map_err(|e| error(&e))?));
+    }
+    if let Some(chunk) = info.uncompressed_latin1_text.iter().find(|chunk| chunk.keyword == keyword) {
+        return Ok(Some(chunk.text.clone()));
+    }
+    Ok(None)
+}
+
+#[inline]
+fn to_u8(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[inline]
+fn to_u16(c: f32) -> u16 {
+    (c.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+// ===== PngRowWriter ==========================================================================================================================================
+
+impl PngRowWriter {
+    /// Creates the PNG file at `path` and writes its header and text chunks.
+    pub fn create(path: &Path, width: u32, height: u32, bit_depth: BitDepth, text: &[TextChunk]) -> Result<PngRowWriter, Box<dyn Error>> {
+        let stream = png_writer(path, width, height, png::ColorType::Rgb, bit_depth, text)?.into_stream_writer()?;
+
+        Ok(PngRowWriter { stream, bit_depth, row: Vec::new() })
+    }
+
+    /// Writes the rows of an image strip, given as the pixels of each tile in the strip. Each tile
+    /// holds `tile_widths[i]` columns and the same number of rows.
+    pub fn write_tiles(&mut self, tiles: &[Vec<Rgb<f32>>], tile_widths: &[usize]) -> Result<(), Box<dyn Error>> {
+        let rows = tiles.first().map_or(0, |tile| tile.len() / tile_widths[0]);
+        for y in 0..rows {
+            self.row.clear();
+            for (colors, &tile_width) in tiles.iter().zip(tile_widths) {
+                for color in &colors[y * tile_width..(y + 1) * tile_width] {
+                    match self.bit_depth {
+                        BitDepth::Sixteen => self.row.extend(color.0.iter().flat_map(|&c| to_u16(c).to_be_bytes())),
+                        _ => self.row.extend(color.0.map(to_u8)),
+                    }
+                }
+            }
+            self.stream.write_all(&self.row)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the image data and closes the file. All rows must have been written.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        Ok(self.stream.finish()?)
+    }
+}
+
+// ===== Y4mWriter =============================================================================================================================================
+
+impl Y4mWriter {
+    /// Creates the stream at `path`, or on standard output if `path` is `-`, and writes its header.
+    pub fn create(path: &Path, width: u32, height: u32, frame_rate: f64) -> Result<Y4mWriter, Box<dyn Error>> {
+        let mut writer: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(path).map_err(|e| format!("cannot create {}: {e}", path.display()))?))
+        };
+
+        let (numerator, denominator) = frame_rate_ratio(frame_rate)?;
+        writeln!(writer, "YUV4MPEG2 W{width} H{height} F{numerator}:{denominator} Ip A1:1 C420jpeg XCOLORRANGE=LIMITED")?;
+
+        Ok(Y4mWriter { writer, width, height })
+    }
+
+    /// Converts an image to Y'CbCr and writes it as the next frame.
+    pub fn write_frame(&mut self, image: &Rgb32FImage) -> Result<(), Box<dyn Error>> {
+        let (width, height) = (self.width, self.height);
+        if image.dimensions() != (width, height) {
+            return Err(format!("frame is {}x{}, but the stream is {width}x{height}", image.width(), image.height()).into());
+        }
+
+        let luma_plane: Vec<u8> = image.pixels().map(|pixel| to_limited_range(16.0, 219.0, luma(pixel))).collect();
+
+        // Each chroma sample covers a block of 2x2 pixels (less at the right and bottom edges of an
+        // image with an odd size), and is computed from the average color of the block.
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let mut cb = Vec::with_capacity((chroma_width * chroma_height) as usize);
+        let mut cr = Vec::with_capacity((chroma_width * chroma_height) as usize);
+        for chroma_y in 0..chroma_height {
+            for chroma_x in 0..chroma_width {
+                let mut sum = [0.0f32; 3];
+                let mut count = 0.0;
+                for y in (chroma_y * 2)..(chroma_y * 2 + 2).min(height) {
+                    for x in (chroma_x * 2)..(chroma_x * 2 + 2).min(width) {
+                        let pixel = image.get_pixel(x, y);
+                        for (total, c) in sum.iter_mut().zip(pixel.0) {
+                            *total += c.clamp(0.0, 1.0);
+                        }
+                        count += 1.0;
+                    }
+                }
+                let average = Rgb(sum.map(|total| total / count));
+                let y = luma(&average);
+                cb.push(to_limited_range(128.0, 224.0, (average[2] - y) / 1.8556));
+                cr.push(to_limited_range(128.0, 224.0, (average[0] - y) / 1.5748));
+            }
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&luma_plane)?;
+        self.writer.write_all(&cb)?;
+        self.writer.write_all(&cr)?;
+        Ok(())
+    }
+
+    /// Flushes the stream. All frames must have been written.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Returns the BT.709 luma of an sRGB encoded color.
+#[inline]
+fn luma(color: &Rgb<f32>) -> f32 {
+    let [r, g, b] = color.0.map(|c| c.clamp(0.0, 1.0));
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Maps a luma value in `0.0..=1.0` or a chroma value in `-0.5..=0.5` to its limited range code.
+#[inline]
+fn to_limited_range(offset: f32, range: f32, value: f32) -> u8 {
+    (offset + range * value).round().clamp(0.0, 255.0) as u8
+}
+
+/// Expresses a frame rate as a ratio for the Y4M header: exactly for whole numbers, as `N000:1001`
+/// for the NTSC rates such as 29.97, and in thousandths otherwise. Rates that would round to zero
+/// are an error.
+fn frame_rate_ratio(frame_rate: f64) -> Result<(u64, u64), String> {
+    let ntsc = (frame_rate * 1.001).round();
+    let ratio = if frame_rate.fract() == 0.0 {
+        (frame_rate as u64, 1)
+    } else if (ntsc / 1.001 - frame_rate).abs() < 1e-3 {
+        (ntsc as u64 * 1000, 1001)
+    } else {
+        ((frame_rate * 1000.0).round() as u64, 1000)
+    };
+    if ratio.0 == 0 {
+        return Err(format!("the frame rate must be at least 0.001 frames per second, got {frame_rate}"));
+    }
+    Ok(ratio)
+}
+
+/// Speed of the color quantizer for GIF animations, from 1 (best quality) to 30 (fastest).
+const GIF_QUANTIZER_SPEED: i32 = 10;
+
+// ===== AnimationWriter =======================================================================================================================================
+
+impl AnimationWriter {
+    /// Creates the animation file at `path` for `frame_count` frames, shown at `frame_rate` frames
+    /// per second. `format` must be PNG or GIF; PNG files get the given text chunks.
+    pub fn create(path: &Path, format: Format, bit_depth: BitDepth, (width, height): (u32, u32), frame_count: u32, frame_rate: f64, text: &[TextChunk]) -> Result<AnimationWriter, Box<dyn Error>> {
+        let encoder = match format {
+            Format::Png => {
+                let mut encoder = png_encoder(path, width, height, png::ColorType::Rgb, bit_depth, text)?;
+                encoder.set_animated(frame_count, 0)?;
+                let (numerator, denominator) = frame_rate_ratio(frame_rate)?;
+                let (delay_numerator, delay_denominator) = match (u16::try_from(denominator), u16::try_from(numerator)) {
+                    (Ok(delay_numerator), Ok(delay_denominator)) => (delay_numerator, delay_denominator),
+                    _ => ((1000.0 / frame_rate).round().clamp(1.0, u16::MAX as f64) as u16, 1000),
+                };
+                encoder.set_frame_delay(delay_numerator, delay_denominator)?;
+                AnimationEncoder::Apng(encoder.write_header()?)
+            }
+            Format::Gif => {
+                let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+                    return Err("GIF animations can be at most 65535 pixels wide and high".into());
+                };
+                frame_rate_ratio(frame_rate)?;
+                let file = BufWriter::new(File::create(path).map_err(|e| format!("cannot create {}: {e}", path.display()))?);
+                let mut encoder = gif::Encoder::new(file, width, height, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                AnimationEncoder::Gif(encoder, (100.0 / frame_rate).round().clamp(1.0, u16::MAX as f64) as u16)
+            }
+            Format::Exr | Format::Hdr => return Err("animations can only be written as PNG (APNG) or GIF".into()),
+        };
+
+        Ok(AnimationWriter { encoder, bit_depth })
+    }
+
+    /// Writes the next frame.
+    pub fn write_frame(&mut self, image: &Rgb32FImage) -> Result<(), Box<dyn Error>> {
+        match &mut self.encoder {
+            AnimationEncoder::Apng(writer) => {
+                let data: Vec<u8> = match self.bit_depth {
+                    BitDepth::Sixteen => image.as_raw().iter().flat_map(|&c| to_u16(c).to_be_bytes()).collect(),
+                    _ => image.as_raw().iter().map(|&c| to_u8(c)).collect(),
+                };
+                writer.write_image_data(&data)?;
+            }
+            AnimationEncoder::Gif(encoder, delay) => {
+                let mut data: Vec<u8> = image.pixels().flat_map(|pixel| pixel.0.map(to_u8).into_iter().chain([255])).collect();
+                let mut frame = gif::Frame::from_rgba_speed(image.width() as u16, image.height() as u16, &mut data, GIF_QUANTIZER_SPEED);
+                frame.delay = *delay;
+                encoder.write_frame(&frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes the animation and closes the file. All frames must have been written.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.encoder {
+            AnimationEncoder::Apng(writer) => writer.finish()?,
+            AnimationEncoder::Gif(encoder, _) => encoder.into_inner()?.flush()?,
+        }
+        Ok(())
+    }
+}
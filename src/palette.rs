@@ -12,128 +12,533 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::ops::Range;
+use std::ops::{Range, RangeInclusive};
 
+use clap::ValueEnum;
 use image::Rgb;
+use serde::{Deserialize, Serialize};
 
-use crate::math::interpolate;
+use crate::math::{Spline, hsl_to_srgb, interpolate, interpolate_hue, linear_to_srgb, oklab_to_oklch, oklab_to_srgb, oklch_to_oklab, srgb_to_hsl, srgb_to_linear, srgb_to_oklab};
 
 pub trait Palette {
-    fn evaluate(&self, value: f64) -> Rgb<u8>;
+    /// Returns the color for a value, with each component in `0.0..=1.0` (sRGB encoded). Colors
+    /// are kept in floating point so that they can be written at more than 8 bits per channel.
+    fn evaluate(&self, value: f64) -> Rgb<f32>;
+
+    /// Returns the range of values that the palette spans.
+    fn range(&self) -> RangeInclusive<f64>;
+
+    /// Returns the opacity for a value, in `0.0..=1.0`. It is only used for output with an alpha
+    /// channel. Palettes are opaque unless they have stops with an alpha.
+    fn alpha(&self, _value: f64) -> f32 {
+        1.0
+    }
 }
 
 pub struct Grayscale {
     range: Range<f64>,
-    scale: f64,
 }
 
 pub struct Rainbow {
     range: Range<f64>,
-    scale: f64,
 }
 
 pub struct Entry {
     value: f64,
-    color: Rgb<u8>,
+    color: Rgb<f32>,
+    alpha: f32,
 }
 
 pub struct TablePalette {
     range: Range<f64>,
     entries: Vec<Entry>,
+    color_space: ColorSpace,
+    coordinates: Vec<[f32; 3]>,
+    splines: Option<[Spline; 3]>,
+}
+
+/// How the table palette interpolates between its stops.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    /// Straight lines between the stops, with a kink at every stop.
+    #[default]
+    Linear,
+    /// A smooth curve through the stops that never overshoots them.
+    Monotone,
+    /// A smooth Catmull-Rom curve through the stops, which can overshoot between them.
+    CatmullRom,
+}
+
+/// The color space that the table palette interpolates between its stops in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    /// sRGB encoded components, as the colors are written.
+    #[default]
+    Srgb,
+    /// Linear light RGB components.
+    Linear,
+    /// OKLab, a perceptually uniform space: no muddy midpoints between complementary colors.
+    Oklab,
+    /// OKLCh, OKLab in polar form: the hue goes around the shorter way.
+    Oklch,
+    /// Hue, saturation and lightness: the hue goes around the shorter way.
+    Hsl,
+}
+
+/// Saturation or chroma below which a color counts as gray, without a hue of its own.
+const ACHROMATIC: f32 = 1e-4;
+
+/// Maps values onto the range of another palette: optionally repeating the palette every `period`
+/// values, shifting it by a phase offset (a fraction of the palette), and bringing values that fall
+/// outside of the palette back in as given by the edge mode.
+pub struct Mapping {
+    palette: Box<dyn Palette + Sync>,
+    period: Option<f64>,
+    offset: f64,
+    edge: Edge,
+}
+
+/// How a palette mapping brings values outside of the palette's range back in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Edge {
+    /// Use the color at the nearest end of the palette.
+    Clamp,
+    /// Start over at the other end of the palette.
+    Wrap,
+    /// Go back and forth through the palette (ping-pong).
+    Mirror,
 }
 
 // ===== Grayscale =============================================================================================================================================
 
 impl Grayscale {
+    /// The values, for a range of `0.0..1.0`, between which the palette is linear in sRGB.
+    pub const KNOTS: [f64; 2] = [0.0, 1.0];
+
+    /// Creates the palette, going from black at the start of `range` to white at its end.
     pub fn new(range: Range<f64>) -> Grayscale {
-        let scale = 255.0 / (range.end - range.start);
+        debug_assert!(range.start < range.end, "range must not be empty");
 
-        Grayscale { range, scale }
+        Grayscale { range }
     }
 }
 
 impl Palette for Grayscale {
-    fn evaluate(&self, value: f64) -> Rgb<u8> {
-        if self.range.contains(&value) {
-            let v = ((value - self.range.start) * self.scale).round() as u8;
+    fn evaluate(&self, value: f64) -> Rgb<f32> {
+        if (self.range.start..=self.range.end).contains(&value) {
+            let v = fraction(&self.range, value) as f32;
             Rgb([v, v, v])
         } else {
-            Rgb([0, 0, 0])
+            Rgb([0.0, 0.0, 0.0])
         }
     }
+
+    fn range(&self) -> RangeInclusive<f64> {
+        self.range.start..=self.range.end
+    }
 }
 
 // ===== Rainbow ===============================================================================================================================================
 
 impl Rainbow {
+    /// The values, for a range of `0.0..1.0`, between which the palette is linear in sRGB.
+    pub const KNOTS: [f64; 6] = [0.0, 0.2, 0.4, 0.6, 0.8, 1.0];
+
+    /// Creates the palette, going from blue at the start of `range` to magenta at its end.
     pub fn new(range: Range<f64>) -> Rainbow {
-        let scale = range.end - range.start;
+        debug_assert!(range.start < range.end, "range must not be empty");
 
-        Rainbow { range, scale }
+        Rainbow { range }
     }
 }
 
 impl Palette for Rainbow {
-    fn evaluate(&self, value: f64) -> Rgb<u8> {
-        let v = (value - self.range.start) * self.scale;
-        if v < 0.0 {
-            Rgb([0, 0, 0])
+    fn evaluate(&self, value: f64) -> Rgb<f32> {
+        // Computed in double precision, so that the ends of each part come out exactly.
+        let v = fraction(&self.range, value);
+        let color = if v < 0.0 {
+            [0.0, 0.0, 0.0]
         } else if v < 0.2 {
-            Rgb([0, (v * 1275.0).round() as u8, 255]) // 0.0..0.2: blue-cyan
+            [0.0, v * 5.0, 1.0] // 0.0..0.2: blue-cyan
         } else if v < 0.4 {
-            Rgb([0, 255, ((0.4 - v) * 1275.0).round() as u8]) // 0.2..0.4: cyan-green
+            [0.0, 1.0, (0.4 - v) * 5.0] // 0.2..0.4: cyan-green
         } else if v < 0.6 {
-            Rgb([((v - 0.4) * 1275.0).round() as u8, 255, 0]) // 0.4..0.6: green-yellow
+            [(v - 0.4) * 5.0, 1.0, 0.0] // 0.4..0.6: green-yellow
         } else if v < 0.8 {
-            Rgb([255, ((0.8 - v) * 1275.0).round() as u8, 0]) // 0.6..0.8: yellow-red
-        } else if v < 1.0 {
-            Rgb([255, 0, ((v - 0.8) * 1275.0).round() as u8]) // 0.8..1.0: red-magenta
+            [1.0, (0.8 - v) * 5.0, 0.0] // 0.6..0.8: yellow-red
+        } else if v <= 1.0 {
+            [1.0, 0.0, (v - 0.8) * 5.0] // 0.8..=1.0: red-magenta
         } else {
-            Rgb([0, 0, 0])
-        }
+            [0.0, 0.0, 0.0]
+        };
+        Rgb(color.map(|c| c as f32))
     }
+
+    fn range(&self) -> RangeInclusive<f64> {
+        self.range.start..=self.range.end
+    }
+}
+
+/// Returns how far `value` is along `range`: 0 at its start and 1 at its end, exactly.
+fn fraction(range: &Range<f64>, value: f64) -> f64 {
+    (value - range.start) / (range.end - range.start)
 }
 
 // ===== Entry =================================================================================================================================================
 
 impl Entry {
     pub fn new(value: f64, color: Rgb<u8>) -> Entry {
-        Entry { value, color }
+        Entry { value, color: Rgb(color.0.map(|c| c as f32 / 255.0)), alpha: 1.0 }
+    }
+
+    /// Returns this entry with an opacity, in `0.0..=1.0`, instead of being opaque.
+    pub fn with_alpha(self, alpha: f32) -> Entry {
+        Entry { alpha, ..self }
     }
 }
 
 // ===== TablePalette ==========================================================================================================================================
 
 impl TablePalette {
-    pub fn new(mut entries: Vec<Entry>) -> TablePalette {
+    /// Creates the palette from entries with values in `0.0..=1.0`, which are spread over `range`.
+    pub fn new(mut entries: Vec<Entry>, range: Range<f64>, color_space: ColorSpace, interpolation: Interpolation) -> TablePalette {
         debug_assert!(!entries.is_empty(), "entries must not be empty");
+        debug_assert!(range.start < range.end, "range must not be empty");
+
+        for entry in &mut entries {
+            // Interpolated this way, the ends of the range are hit exactly.
+            entry.value = range.start * (1.0 - entry.value) + range.end * entry.value;
+        }
+        entries.sort_by(|first, second| first.value.total_cmp(&second.value));
+        let coordinates: Vec<[f32; 3]> = entries.iter().map(|entry| color_space.to_coordinates(entry.color.0)).collect();
+
+        let splines = (interpolation != Interpolation::Linear && entries.len() > 1).then(|| {
+            let times: Vec<f64> = entries.iter().map(|entry| entry.value).collect();
+            let components = color_space.continuous_components(&coordinates);
+            components.map(|values| match interpolation {
+                Interpolation::Monotone => Spline::monotone(times.clone(), values),
+                _ => Spline::catmull_rom(times.clone(), values),
+            })
+        });
 
-        entries.sort_by(|first, second| first.value.partial_cmp(&second.value).unwrap());
-        let range = entries.first().unwrap().value..entries.last().unwrap().value;
+        TablePalette { range, entries, color_space, coordinates, splines }
+    }
 
-        TablePalette { range, entries }
+    /// Returns the range of values from the first stop to the last, which need not cover the whole
+    /// range of the palette.
+    fn stop_range(&self) -> RangeInclusive<f64> {
+        self.entries.first().unwrap().value..=self.entries.last().unwrap().value
     }
 }
 
 impl Palette for TablePalette {
-    fn evaluate(&self, value: f64) -> Rgb<u8> {
-        if self.range.contains(&value) {
-            let mut index = 1;
-            while value > self.entries[index].value {
-                index += 1;
+    fn evaluate(&self, value: f64) -> Rgb<f32> {
+        if self.entries.len() == 1 && value == self.entries[0].value {
+            self.entries[0].color
+        } else if self.stop_range().contains(&value) {
+            let color = match &self.splines {
+                Some(splines) => self.color_space.wrap_hue(splines.each_ref().map(|spline| spline.evaluate(value) as f32)),
+                None => {
+                    let mut index = 1;
+                    while value > self.entries[index].value {
+                        index += 1;
+                    }
+
+                    let left = &self.entries[index - 1];
+                    let right = &self.entries[index];
+                    let value = ((value - left.value) / (right.value - left.value)) as f32;
+
+                    self.color_space.interpolate(value, self.coordinates[index - 1], self.coordinates[index])
+                }
+            };
+            Rgb(self.color_space.to_srgb(color).map(|c| c.clamp(0.0, 1.0)))
+        } else {
+            Rgb([0.0, 0.0, 0.0])
+        }
+    }
+
+    fn range(&self) -> RangeInclusive<f64> {
+        self.range.start..=self.range.end
+    }
+
+    /// The opacity is interpolated linearly between the stops, whatever the interpolation of the
+    /// colors.
+    fn alpha(&self, value: f64) -> f32 {
+        if !self.stop_range().contains(&value) {
+            return 1.0;
+        }
+        if self.entries.len() == 1 {
+            return self.entries[0].alpha;
+        }
+
+        let index = self.entries.iter().skip(1).position(|entry| value <= entry.value).unwrap_or(self.entries.len() - 2) + 1;
+        let left = &self.entries[index - 1];
+        let right = &self.entries[index];
+        interpolate(((value - left.value) / (right.value - left.value)) as f32, left.alpha, right.alpha)
+    }
+}
+
+// ===== ColorSpace ============================================================================================================================================
+
+impl ColorSpace {
+    /// Converts an sRGB encoded color to coordinates in this color space.
+    fn to_coordinates(self, color: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorSpace::Srgb => color,
+            ColorSpace::Linear => color.map(srgb_to_linear),
+            ColorSpace::Oklab => srgb_to_oklab(color),
+            ColorSpace::Oklch => oklab_to_oklch(srgb_to_oklab(color)),
+            ColorSpace::Hsl => srgb_to_hsl(color),
+        }
+    }
+
+    /// Converts coordinates in this color space to an sRGB encoded color.
+    fn to_srgb(self, coordinates: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorSpace::Srgb => coordinates,
+            ColorSpace::Linear => coordinates.map(|c| linear_to_srgb(c.clamp(0.0, 1.0))),
+            ColorSpace::Oklab => oklab_to_srgb(coordinates),
+            ColorSpace::Oklch => oklab_to_srgb(oklch_to_oklab(coordinates)),
+            ColorSpace::Hsl => hsl_to_srgb(coordinates),
+        }
+    }
+
+    /// Interpolates between two colors in this color space. The hue of a polar space takes the
+    /// shorter way around; a gray has no hue of its own and takes the hue of the other color.
+    fn interpolate(self, value: f32, mut left: [f32; 3], mut right: [f32; 3]) -> [f32; 3] {
+        let Some((hue, chroma)) = self.hue_and_chroma() else {
+            return [0, 1, 2].map(|i| interpolate(value, left[i], right[i]));
+        };
+
+        if left[chroma] < ACHROMATIC {
+            left[hue] = right[hue];
+        } else if right[chroma] < ACHROMATIC {
+            right[hue] = left[hue];
+        }
+        [0, 1, 2].map(|i| if i == hue { interpolate_hue(value, left[i], right[i]) } else { interpolate(value, left[i], right[i]) })
+    }
+
+    /// Returns the index of the hue in the coordinates of a polar color space, and the index of the
+    /// saturation or chroma, which tells whether there is a hue at all.
+    fn hue_and_chroma(self) -> Option<(usize, usize)> {
+        match self {
+            ColorSpace::Oklch => Some((2, 1)),
+            ColorSpace::Hsl => Some((0, 1)),
+            ColorSpace::Srgb | ColorSpace::Linear | ColorSpace::Oklab => None,
+        }
+    }
+
+    /// Splits the coordinates of a sequence of colors into one sequence per component, for a spline
+    /// through them. Hues are unwrapped, so that they take the shorter way from color to color
+    /// without jumping at the wrap around; a gray takes the hue of the color before it, or after it
+    /// if it comes first.
+    fn continuous_components(self, coordinates: &[[f32; 3]]) -> [Vec<f64>; 3] {
+        let mut components = [0, 1, 2].map(|i| coordinates.iter().map(|color| color[i] as f64).collect::<Vec<f64>>());
+
+        if let Some((hue, chroma)) = self.hue_and_chroma() {
+            if let Some(first) = coordinates.iter().find(|color| color[chroma] >= ACHROMATIC) {
+                let mut previous = first[hue] as f64;
+                for (unwrapped, color) in components[hue].iter_mut().zip(coordinates) {
+                    let own = if color[chroma] >= ACHROMATIC { *unwrapped } else { previous };
+                    let difference = own - previous;
+                    previous += difference - difference.round();
+                    *unwrapped = previous;
+                }
             }
+        }
+        components
+    }
+
+    /// Brings an unwrapped hue back to `0.0..1.0`.
+    fn wrap_hue(self, mut coordinates: [f32; 3]) -> [f32; 3] {
+        if let Some((hue, _)) = self.hue_and_chroma() {
+            coordinates[hue] = coordinates[hue].rem_euclid(1.0);
+        }
+        coordinates
+    }
+}
+
+// ===== Mapping ===============================================================================================================================================
 
-            let left = &self.entries[index - 1];
-            let right = &self.entries[index];
-            let value = (value - left.value) / (right.value - left.value);
+impl Mapping {
+    /// Creates a mapping onto `palette`. With a `period`, the palette is repeated every `period`
+    /// values, starting at 0; without one, the palette spans its own range once.
+    pub fn new(palette: Box<dyn Palette + Sync>, period: Option<f64>, offset: f64, edge: Edge) -> Mapping {
+        Mapping { palette, period, offset, edge }
+    }
 
-            let r = interpolate(value, left.color[0] as f64, right.color[0] as f64).clamp(0.0, 255.0).round() as u8;
-            let g = interpolate(value, left.color[1] as f64, right.color[1] as f64).clamp(0.0, 255.0).round() as u8;
-            let b = interpolate(value, left.color[2] as f64, right.color[2] as f64).clamp(0.0, 255.0).round() as u8;
+    /// Returns the value within the range of the palette that `value` is mapped to.
+    fn palette_value(&self, value: f64) -> f64 {
+        let range = self.palette.range();
+        let (start, end) = (*range.start(), *range.end());
 
-            Rgb([r, g, b])
-        } else {
-            Rgb([0, 0, 0])
+        // The position within the palette, in units of the whole palette.
+        let position = match self.period {
+            Some(period) => value / period,
+            None => (value - start) / (end - start),
+        } + self.offset;
+
+        let position = match self.edge {
+            Edge::Clamp => position.clamp(0.0, 1.0),
+            // The top of the range keeps the color at the end, rather than wrapping around to the
+            // start, so that the maximum value does not stand out.
+            Edge::Wrap if position > 0.0 && position.rem_euclid(1.0) == 0.0 => 1.0,
+            Edge::Wrap => position.rem_euclid(1.0),
+            Edge::Mirror => 1.0 - (position.rem_euclid(2.0) - 1.0).abs(),
+        };
+        // Interpolated this way, the ends of the range are hit exactly, and never overshot.
+        start * (1.0 - position) + end * position
+    }
+}
+
+impl Palette for Mapping {
+    fn evaluate(&self, value: f64) -> Rgb<f32> {
+        self.palette.evaluate(self.palette_value(value))
+    }
+
+    fn range(&self) -> RangeInclusive<f64> {
+        self.palette.range()
+    }
+
+    fn alpha(&self, value: f64) -> f32 {
+        self.palette.alpha(self.palette_value(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR_SPACES: [ColorSpace; 5] = [ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Oklab, ColorSpace::Oklch, ColorSpace::Hsl];
+    const INTERPOLATIONS: [Interpolation; 3] = [Interpolation::Linear, Interpolation::Monotone, Interpolation::CatmullRom];
+
+    /// Stops with unevenly spaced values, a gray, and hues on both sides of the wrap around.
+    fn stops() -> Vec<(f64, Rgb<u8>)> {
+        vec![
+            (0.0, Rgb([0, 0, 102])),
+            (0.01, Rgb([25, 25, 25])),
+            (0.018, Rgb([255, 255, 76])),
+            (0.2, Rgb([255, 0, 64])),
+            (0.35, Rgb([200, 0, 255])),
+            (0.5, Rgb([0, 0, 0])),
+            (1.0, Rgb([255, 255, 255])),
+        ]
+    }
+
+    fn palette(color_space: ColorSpace, interpolation: Interpolation) -> TablePalette {
+        TablePalette::new(stops().into_iter().map(|(value, color)| Entry::new(value, color)).collect(), 0.0..1.0, color_space, interpolation)
+    }
+
+    #[test]
+    fn table_palette_passes_through_each_stop() {
+        for color_space in COLOR_SPACES {
+            for interpolation in INTERPOLATIONS {
+                let palette = palette(color_space, interpolation);
+                for (value, color) in stops() {
+                    let expected = color.0.map(|c| c as f32 / 255.0);
+                    let actual = palette.evaluate(value).0;
+                    if color_space == ColorSpace::Srgb {
+                        assert_eq!(actual, expected, "{color_space:?}, {interpolation:?}, stop at {value}");
+                    } else {
+                        // Other color spaces convert back and forth, which costs a little precision.
+                        let error = actual.iter().zip(expected).map(|(a, e)| (a - e).abs()).fold(0.0, f32::max);
+                        assert!(error < 1e-4, "{color_space:?}, {interpolation:?}, stop at {value}: {actual:?} != {expected:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn monotone_interpolation_stays_between_stops() {
+        let palette = palette(ColorSpace::Srgb, Interpolation::Monotone);
+        let stops = stops();
+        for pair in stops.windows(2) {
+            let (left, right) = ((pair[0].0, pair[0].1.0.map(|c| c as f32 / 255.0)), (pair[1].0, pair[1].1.0.map(|c| c as f32 / 255.0)));
+            for step in 1..100 {
+                let value = left.0 + (right.0 - left.0) * step as f64 / 100.0;
+                let color = palette.evaluate(value).0;
+                for (i, component) in color.into_iter().enumerate() {
+                    let (low, high) = (left.1[i].min(right.1[i]), left.1[i].max(right.1[i]));
+                    assert!((low..=high).contains(&component), "component {i} at {value}: {component} outside {low}..={high}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn linear_interpolation_is_halfway_between_stops() {
+        let palette = TablePalette::new(vec![Entry::new(0.0, Rgb([0, 100, 200])), Entry::new(1.0, Rgb([200, 100, 0]))], 0.0..1.0, ColorSpace::Srgb, Interpolation::Linear);
+        assert_eq!(palette.evaluate(0.5).0, [100.0 / 255.0, 100.0 / 255.0, 100.0 / 255.0]);
+    }
+
+    #[test]
+    fn wrapping_keeps_the_end_color_at_the_top_of_the_range() {
+        let mapping = Mapping::new(Box::new(Grayscale::new(0.0..1.0)), None, 0.0, Edge::Wrap);
+        assert_eq!(mapping.evaluate(0.0).0, [0.0, 0.0, 0.0]);
+        assert_eq!(mapping.evaluate(1.0).0, [1.0, 1.0, 1.0]);
+        assert_eq!(mapping.evaluate(1.25).0, [0.25, 0.25, 0.25]);
+        assert_eq!(mapping.evaluate(-0.75).0, [0.25, 0.25, 0.25]);
+
+        let periodic = Mapping::new(Box::new(Grayscale::new(0.0..1.0)), Some(0.5), 0.0, Edge::Wrap);
+        assert_eq!(periodic.evaluate(0.5).0, [1.0, 1.0, 1.0]);
+        assert_eq!(periodic.evaluate(0.75).0, [0.5, 0.5, 0.5]);
+    }
+
+    /// Ranges of values to spread the palettes over, including ones that do not start at 0 and whose
+    /// widths are not exact in binary.
+    const RANGES: [Range<f64>; 4] = [0.0..1.0, 0.25..0.75, 0.1..0.7, -2.0..3.0];
+
+    /// Asserts that `palette` has the colors `first` and `last` at the ends of `range`, spans exactly
+    /// that range, and is black just outside of it.
+    fn assert_endpoints(palette: &dyn Palette, range: &Range<f64>, first: [f32; 3], last: [f32; 3]) {
+        assert_eq!(palette.range(), range.start..=range.end);
+        assert_eq!(palette.evaluate(range.start).0, first, "start of {range:?}");
+        assert_eq!(palette.evaluate(range.end).0, last, "end of {range:?}");
+        let outside = 1e-9 * (range.end - range.start);
+        assert_eq!(palette.evaluate(range.start - outside).0, [0.0, 0.0, 0.0], "before {range:?}");
+        assert_eq!(palette.evaluate(range.end + outside).0, [0.0, 0.0, 0.0], "after {range:?}");
+    }
+
+    #[test]
+    fn grayscale_goes_from_black_to_white_over_its_range() {
+        for range in RANGES {
+            let palette = Grayscale::new(range.clone());
+            assert_endpoints(&palette, &range, [0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+            assert_eq!(palette.evaluate((range.start + range.end) / 2.0).0, [0.5, 0.5, 0.5], "middle of {range:?}");
+        }
+    }
+
+    #[test]
+    fn rainbow_goes_from_blue_to_magenta_over_its_range() {
+        for range in RANGES {
+            let palette = Rainbow::new(range.clone());
+            assert_endpoints(&palette, &range, [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]);
+            let [r, g, b] = palette.evaluate((range.start + range.end) / 2.0).0;
+            assert!((r - 0.5).abs() < 1e-5 && g == 1.0 && b == 0.0, "middle of {range:?}: {:?}", [r, g, b]);
         }
     }
+
+    #[test]
+    fn table_palette_spreads_its_stops_over_its_range() {
+        for range in RANGES {
+            for interpolation in INTERPOLATIONS {
+                let entries = stops().into_iter().map(|(value, color)| Entry::new(value, color)).collect();
+                let palette = TablePalette::new(entries, range.clone(), ColorSpace::Srgb, interpolation);
+                assert_endpoints(&palette, &range, [0.0, 0.0, 102.0 / 255.0], [1.0, 1.0, 1.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn table_palette_reports_its_range_when_the_stops_do_not_cover_it() {
+        let entries = vec![Entry::new(0.25, Rgb([255, 0, 0])), Entry::new(0.75, Rgb([0, 0, 255]))];
+        let palette = TablePalette::new(entries, 0.0..2.0, ColorSpace::Srgb, Interpolation::Linear);
+        assert_eq!(palette.range(), 0.0..=2.0);
+        assert_eq!(palette.evaluate(0.5).0, [1.0, 0.0, 0.0]);
+        assert_eq!(palette.evaluate(0.25).0, [0.0, 0.0, 0.0]);
+    }
 }
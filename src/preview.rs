@@ -0,0 +1,158 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Palette previews: a strip with the colors of a palette, its stops, and the distribution of the
+//! values of an image over it; and export of any palette as a palette file.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+use clap::ValueEnum;
+use image::{Rgb, Rgb32FImage};
+
+use crate::output::save_image;
+use crate::palette::{ColorSpace, Grayscale, Interpolation, Palette, Rainbow};
+use crate::values::ValueFile;
+use crate::{Args, PaletteArgs, PaletteFile, PaletteKind, Sink, StopEntry, build_palette, build_transfer, check_palette_name, palette_range, render_to, table_palette, value_file_values};
+
+/// Height of the stop markers, as a fraction of the height of the palette.
+const MARKER_HEIGHT: f64 = 0.25;
+
+/// Draws the palette of `args` as a horizontal strip of `width` by `height` pixels, from value 0 on
+/// the left to 1 on the right, and writes it to the output path.
+///
+/// The stops of the palette are marked along the bottom edge, where its range puts them, unless a
+/// palette mapping moves them around. With `distribution`, the image of `args` is rendered, and the
+/// lower half of the strip shows how its values are spread over the palette after the transfer
+/// function.
+pub fn draw_strip(args: &Args, width: u32, height: u32, distribution: bool) -> Result<(), Box<dyn Error>> {
+    if width < 2 || height < 2 {
+        return Err("the strip must be at least 2 by 2 pixels".into());
+    }
+    let (format, bit_depth) = args.output.resolve()?;
+    let palette = build_palette(&args.palette, args.max_iterations)?;
+
+    let counts = if distribution { Some(value_counts(args, width)?) } else { None };
+    let palette_height = if counts.is_some() { height / 2 } else { height };
+
+    let value = |x: u32| x as f64 / (width - 1) as f64;
+    let mut image = Rgb32FImage::from_fn(width, height, |x, y| if y < palette_height { palette.evaluate(value(x)) } else { Rgb([0.1, 0.1, 0.1]) });
+
+    let mapped = args.palette.palette_offset != 0.0 || args.palette.palette_period.is_some() || args.palette.palette_edge.is_some();
+    if !mapped {
+        let marker_top = palette_height - (palette_height as f64 * MARKER_HEIGHT).ceil() as u32;
+        let range = palette_range(&args.palette)?;
+        let stops = palette_stops(&args.palette)?.stops.iter().map(|stop| range.start * (1.0 - stop.value) + range.end * stop.value).collect::<Vec<_>>();
+        for value in stops.into_iter().filter(|value| (0.0..=1.0).contains(value)) {
+            let x = (value * (width - 1) as f64).round() as u32;
+            let color = contrasting(palette.evaluate(value));
+            for y in marker_top..palette_height {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    if let Some(counts) = counts {
+        let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+        let band_height = height - palette_height;
+        for (x, &count) in counts.iter().enumerate() {
+            let bar_height = (count as f64 / max_count as f64 * band_height as f64).round() as u32;
+            for y in height - bar_height..height {
+                image.put_pixel(x as u32, y, Rgb([0.8, 0.8, 0.8]));
+            }
+        }
+    }
+
+    save_image(&image, &args.output.output, format, bit_depth, &[])
+}
+
+/// Renders the image of `args`, and counts its values after the transfer function in `bins` equal
+/// bins over `0.0..=1.0`. Values outside of that range are not counted.
+fn value_counts(args: &Args, bins: u32) -> Result<Vec<u64>, Box<dyn Error>> {
+    let values = RefCell::new(None);
+    render_to(args, Some(Sink::Values(&values)))?;
+    let values: ValueFile = values.into_inner().expect("rendered values");
+
+    let transfer = build_transfer(&args.palette, values.view().max_iterations)?;
+    let mut counts = vec![0; bins as usize];
+    for value in transfer.apply_all(&value_file_values(&values)).into_iter().flatten() {
+        if (0.0..=1.0).contains(&value) {
+            counts[((value * bins as f64) as usize).min(bins as usize - 1)] += 1;
+        }
+    }
+    Ok(counts)
+}
+
+/// Returns black or white, whichever stands out more against `color`.
+fn contrasting(color: Rgb<f32>) -> Rgb<f32> {
+    let [r, g, b] = color.0;
+    if 0.299 * r + 0.587 * g + 0.114 * b > 0.5 { Rgb([0.0, 0.0, 0.0]) } else { Rgb([1.0, 1.0, 1.0]) }
+}
+
+/// Returns the palette as color stops: the stops of the table palette, or the values where the
+/// grayscale and rainbow palettes, which are linear in sRGB between them, change direction.
+fn palette_stops(args: &PaletteArgs) -> Result<PaletteFile, Box<dyn Error>> {
+    check_palette_name(args)?;
+    let knots = |palette: &dyn Palette, knots: &[f64]| {
+        let stops = knots.iter().map(|&value| StopEntry { value, color: hex_color(palette.evaluate(value)), alpha: None }).collect();
+        PaletteFile { stops, color_space: ColorSpace::Srgb, interpolation: Interpolation::Linear }
+    };
+    Ok(match args.palette {
+        PaletteKind::Table => table_palette(args)?,
+        PaletteKind::Grayscale => knots(&Grayscale::new(0.0..1.0), &Grayscale::KNOTS),
+        PaletteKind::Rainbow => knots(&Rainbow::new(0.0..1.0), &Rainbow::KNOTS),
+    })
+}
+
+fn hex_color(color: Rgb<f32>) -> String {
+    let [r, g, b] = color.0.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{r:02X}{g:02X}{b:02X}")
+}
+
+// ===== Export ================================================================================================================================================
+
+/// Writes the palette of `args` as a palette file in the format of `palette.toml`, to `path` or to
+/// standard output if it is `-`. The range, mapping and transfer function are not part of it.
+pub fn export_palette(args: &PaletteArgs, path: &Path) -> Result<(), Box<dyn Error>> {
+    let table = palette_stops(args)?;
+    let kind = args.palette.to_possible_value().expect("palette name");
+    let name = args.palette_name.as_deref().unwrap_or(kind.get_name());
+    let file_name = if path == Path::new("-") { "palette.toml".into() } else { path.file_name().unwrap_or_default().to_string_lossy() };
+
+    let mut text = String::new();
+    writeln!(text, "# The {} palette, exported by mandelbrot {}. Use it with:", name, env!("CARGO_PKG_VERSION"))?;
+    writeln!(text, "#")?;
+    writeln!(text, "#     mandelbrot --palette table --palette-file {file_name}")?;
+    writeln!(text)?;
+    writeln!(text, "color_space = {}", toml::Value::try_from(table.color_space)?)?;
+    writeln!(text, "interpolation = {}", toml::Value::try_from(table.interpolation)?)?;
+    writeln!(text)?;
+    writeln!(text, "stops = [")?;
+    for stop in &table.stops {
+        match stop.alpha {
+            Some(alpha) => writeln!(text, "    {{ value = {:?}, color = \"{}\", alpha = {:?} }},", stop.value, stop.color, alpha)?,
+            None => writeln!(text, "    {{ value = {:?}, color = \"{}\" }},", stop.value, stop.color)?,
+        }
+    }
+    writeln!(text, "]")?;
+
+    if path == Path::new("-") {
+        print!("{text}");
+    } else {
+        std::fs::write(path, text).map_err(|e| format!("cannot write palette file {}: {e}", path.display()))?;
+    }
+    Ok(())
+}
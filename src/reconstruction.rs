@@ -27,9 +27,14 @@ where
 {
     accumulator: R,
     total_weight: f64,
+    total_abs_weight: f64,
     filter: &'a F,
 }
 
+/// The smallest total weight, relative to the sum of the absolute weights, that [`Reconstructor::value`]
+/// still divides by.
+const MIN_RELATIVE_WEIGHT: f64 = 1e-3;
+
 // ===== RendererOutput ========================================================================================================================================
 
 impl<T: Copy + Default + AddAssign + Mul<f64, Output=Self> + Div<f64, Output=Self> + Send + Sync> RendererOutput for T {}
@@ -43,7 +48,7 @@ where
 {
     #[inline]
     pub fn new(filter: &'a F) -> Reconstructor<'a, R, F> {
-        Reconstructor { accumulator: R::default(), total_weight: 0.0, filter }
+        Reconstructor { accumulator: R::default(), total_weight: 0.0, total_abs_weight: 0.0, filter }
     }
 
     /// Accumulates a sample value with a weight determined by the reconstruction filter. `dx` and
@@ -55,12 +60,17 @@ where
         let weight = self.filter.evaluate(dx, dy);
         self.accumulator += value * weight;
         self.total_weight += weight;
+        self.total_abs_weight += weight.abs();
     }
 
     /// Returns the filtered value, or `None` when no sample contributed any weight (for the
     /// Mandelbrot renderer this means every nearby sample was inside the set).
+    ///
+    /// Filters with negative lobes (Mitchell, Catmull-Rom, Lanczos) can produce a total weight that
+    /// is close to zero or even negative, when only samples in the negative lobes contributed.
+    /// Dividing by it would blow the value up, so such pixels are treated as having no value too.
     #[inline]
     pub fn value(self) -> Option<R> {
-        if self.total_weight != 0.0 { Some(self.accumulator / self.total_weight) } else { None }
+        if self.total_weight > MIN_RELATIVE_WEIGHT * self.total_abs_weight { Some(self.accumulator / self.total_weight) } else { None }
     }
 }
@@ -12,12 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::math::interpolate;
+
 pub trait Filter {
     /// The radius of the filter's support in pixels, along the x and y axes. Reconstruction uses
     /// this to decide which samples around a pixel can contribute to it.
     fn radius(&self) -> (f64, f64);
 
     fn evaluate(&self, x: f64, y: f64) -> f64;
+
+    /// Whether the filter is separable: `evaluate(x, y)` is the product of a function of `x` and a
+    /// function of `y`, and both are symmetric around zero. A separable filter can be replaced by a
+    /// pair of 1D lookup tables (see [`TableFilter`]).
+    #[inline]
+    fn separable(&self) -> bool {
+        false
+    }
 }
 
 pub struct BoxFilter {
@@ -32,6 +42,37 @@ pub struct MitchellFilter {
     p2: [f64; 4],
 }
 
+pub struct GaussianFilter {
+    radius_x: f64,
+    radius_y: f64,
+    alpha: f64,
+    exp_x: f64,
+    exp_y: f64,
+}
+
+pub struct LanczosFilter {
+    radius_x: f64,
+    radius_y: f64,
+    tau: f64,
+}
+
+pub struct CatmullRomFilter {
+    mitchell: MitchellFilter,
+}
+
+pub struct TriangleFilter {
+    radius_x: f64,
+    radius_y: f64,
+}
+
+pub struct TableFilter {
+    radius_x: f64,
+    radius_y: f64,
+    table_x: Vec<f64>,
+    table_y: Vec<f64>,
+    interpolate: bool,
+}
+
 // ===== BoxFilter =============================================================================================================================================
 
 impl BoxFilter {
@@ -56,6 +97,11 @@ impl Filter for BoxFilter {
     fn evaluate(&self, x: f64, y: f64) -> f64 {
         if x.abs() <= self.radius_x && y.abs() <= self.radius_y { 1.0 } else { 0.0 }
     }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        true
+    }
 }
 
 // ===== MitchellFilter ========================================================================================================================================
@@ -95,4 +141,217 @@ impl Filter for MitchellFilter {
     fn evaluate(&self, x: f64, y: f64) -> f64 {
         self.mitchell(x / self.radius_x) * self.mitchell(y / self.radius_y)
     }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        true
+    }
+}
+
+// ===== GaussianFilter ========================================================================================================================================
+
+impl GaussianFilter {
+    /// Creates a Gaussian filter with falloff rate `alpha`; larger values give a narrower, sharper
+    /// filter. The Gaussian is shifted down by its value at the radius, so that it goes to zero at
+    /// the edge of its support instead of being cut off abruptly.
+    pub fn new(radius_x: f64, radius_y: f64, alpha: f64) -> GaussianFilter {
+        let exp_x = (-alpha * radius_x * radius_x).exp();
+        let exp_y = (-alpha * radius_y * radius_y).exp();
+
+        GaussianFilter { radius_x, radius_y, alpha, exp_x, exp_y }
+    }
+
+    pub fn with_defaults() -> GaussianFilter {
+        GaussianFilter::new(1.5, 1.5, 2.0)
+    }
+
+    #[inline]
+    fn gaussian(&self, v: f64, exp_radius: f64) -> f64 {
+        ((-self.alpha * v * v).exp() - exp_radius).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    #[inline]
+    fn radius(&self) -> (f64, f64) {
+        (self.radius_x, self.radius_y)
+    }
+
+    #[inline]
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.gaussian(x, self.exp_x) * self.gaussian(y, self.exp_y)
+    }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        true
+    }
+}
+
+// ===== LanczosFilter =========================================================================================================================================
+
+impl LanczosFilter {
+    /// Creates a windowed-sinc filter: a sinc function multiplied by a wider sinc window that
+    /// stretches over `tau` lobes. Sharper than the other filters, at the cost of some ringing near
+    /// hard edges.
+    pub fn new(radius_x: f64, radius_y: f64, tau: f64) -> LanczosFilter {
+        LanczosFilter { radius_x, radius_y, tau }
+    }
+
+    pub fn with_defaults() -> LanczosFilter {
+        LanczosFilter::new(3.0, 3.0, 3.0)
+    }
+
+    #[inline]
+    fn windowed_sinc(&self, v: f64, radius: f64) -> f64 {
+        if v.abs() > radius { 0.0 } else { sinc(v) * sinc(v / self.tau) }
+    }
+}
+
+impl Filter for LanczosFilter {
+    #[inline]
+    fn radius(&self) -> (f64, f64) {
+        (self.radius_x, self.radius_y)
+    }
+
+    #[inline]
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.windowed_sinc(x, self.radius_x) * self.windowed_sinc(y, self.radius_y)
+    }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        true
+    }
+}
+
+#[inline]
+fn sinc(v: f64) -> f64 {
+    let x = std::f64::consts::PI * v;
+    if x.abs() < 1e-5 { 1.0 - x * x / 6.0 } else { x.sin() / x }
+}
+
+// ===== CatmullRomFilter ======================================================================================================================================
+
+impl CatmullRomFilter {
+    /// Creates a Catmull-Rom filter: the Mitchell-Netravali family member with B = 0 and C = 1/2.
+    /// It is sharper than the default Mitchell filter, with slightly more ringing.
+    pub fn new(radius_x: f64, radius_y: f64) -> CatmullRomFilter {
+        CatmullRomFilter { mitchell: MitchellFilter::new(radius_x, radius_y, 0.0, 0.5) }
+    }
+
+    pub fn with_defaults() -> CatmullRomFilter {
+        CatmullRomFilter::new(2.0, 2.0)
+    }
+}
+
+impl Filter for CatmullRomFilter {
+    #[inline]
+    fn radius(&self) -> (f64, f64) {
+        self.mitchell.radius()
+    }
+
+    #[inline]
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell.evaluate(x, y)
+    }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        self.mitchell.separable()
+    }
+}
+
+// ===== TriangleFilter ========================================================================================================================================
+
+impl TriangleFilter {
+    #[inline]
+    pub fn new(radius_x: f64, radius_y: f64) -> TriangleFilter {
+        TriangleFilter { radius_x, radius_y }
+    }
+
+    #[inline]
+    pub fn with_defaults() -> TriangleFilter {
+        TriangleFilter::new(1.0, 1.0)
+    }
+}
+
+impl Filter for TriangleFilter {
+    #[inline]
+    fn radius(&self) -> (f64, f64) {
+        (self.radius_x, self.radius_y)
+    }
+
+    #[inline]
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        (self.radius_x - x.abs()).max(0.0) * (self.radius_y - y.abs()).max(0.0)
+    }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        true
+    }
+}
+
+// ===== TableFilter ===========================================================================================================================================
+
+impl TableFilter {
+    /// Creates a filter that looks up the weights of a separable `filter` in two precomputed 1D
+    /// tables of `size` entries, one per axis, covering `0..=radius`. With `interpolate` the
+    /// weights are interpolated linearly between table entries, otherwise the nearest entry is used.
+    ///
+    /// The tables are derived from `filter.evaluate` alone: `evaluate(x, 0) * evaluate(0, y)` equals
+    /// `evaluate(x, y) * evaluate(0, 0)` for a separable filter, so dividing the y table by the
+    /// center weight recovers the product.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `filter` is not separable, if its center weight is zero or if `size` is less than 2.
+    pub fn new<F: Filter>(filter: &F, size: usize, interpolate: bool) -> TableFilter {
+        assert!(filter.separable(), "filter must be separable");
+        assert!(size >= 2, "table size must be at least 2, got {size}");
+        let center = filter.evaluate(0.0, 0.0);
+        assert!(center != 0.0, "filter must have a non-zero weight at its center");
+
+        let (radius_x, radius_y) = filter.radius();
+        let step_x = radius_x / (size - 1) as f64;
+        let step_y = radius_y / (size - 1) as f64;
+        let table_x = (0..size).map(|i| filter.evaluate(i as f64 * step_x, 0.0)).collect();
+        let table_y = (0..size).map(|i| filter.evaluate(0.0, i as f64 * step_y) / center).collect();
+
+        TableFilter { radius_x, radius_y, table_x, table_y, interpolate }
+    }
+
+    #[inline]
+    fn lookup(&self, table: &[f64], v: f64, radius: f64) -> f64 {
+        let v = v.abs();
+        if v > radius {
+            return 0.0;
+        }
+
+        let position = v / radius * (table.len() - 1) as f64;
+        if self.interpolate {
+            let index = (position as usize).min(table.len() - 2);
+            interpolate(position - index as f64, table[index], table[index + 1])
+        } else {
+            table[position.round() as usize]
+        }
+    }
+}
+
+impl Filter for TableFilter {
+    #[inline]
+    fn radius(&self) -> (f64, f64) {
+        (self.radius_x, self.radius_y)
+    }
+
+    #[inline]
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.lookup(&self.table_x, x, self.radius_x) * self.lookup(&self.table_y, y, self.radius_y)
+    }
+
+    #[inline]
+    fn separable(&self) -> bool {
+        true
+    }
 }
@@ -24,12 +24,16 @@ pub struct MandelbrotRenderer {
     offset_im: f64,
     scale_re: f64,
     scale_im: f64,
+    center: Complex64,
+    rotation: Option<Complex64>,
 }
 
 // ===== MandelbrotRenderer ====================================================================================================================================
 
 impl MandelbrotRenderer {
-    pub fn new(center: Complex64, scale: f64, max_iterations: u64, width: u32, height: u32) -> MandelbrotRenderer {
+    /// Creates a renderer for a view of `width` by `height` pixels. The view is rotated
+    /// counterclockwise around its center by `rotation` degrees.
+    pub fn new(center: Complex64, scale: f64, rotation: f64, max_iterations: u64, width: u32, height: u32) -> MandelbrotRenderer {
         let aspect_ratio = width as f64 / height as f64;
         let (aspect_x, aspect_y) = if aspect_ratio >= 1.0 { (1.0, 1.0 / aspect_ratio) } else { (1.0 / aspect_ratio, 1.0) };
 
@@ -42,7 +46,10 @@ impl MandelbrotRenderer {
         let scale_re = (max_c.re - min_c.re) / width as f64;
         let scale_im = (max_c.im - min_c.im) / height as f64;
 
-        MandelbrotRenderer { max_iterations, offset_re, offset_im, scale_re, scale_im }
+        // Without rotation, points are computed exactly as before rotation was supported.
+        let rotation = (rotation != 0.0).then(|| Complex64::from_polar(1.0, rotation.to_radians()));
+
+        MandelbrotRenderer { max_iterations, offset_re, offset_im, scale_re, scale_im, center, rotation }
     }
 }
 
@@ -51,7 +58,10 @@ impl Renderer for MandelbrotRenderer {
 
     fn render(&self, sample: &Sample) -> Option<Self::Output> {
         let (x, y) = sample.location();
-        let c = Complex64::new(self.offset_re + x * self.scale_re, self.offset_im - y * self.scale_im);
+        let mut c = Complex64::new(self.offset_re + x * self.scale_re, self.offset_im - y * self.scale_im);
+        if let Some(rotation) = self.rotation {
+            c = self.center + (c - self.center) * rotation;
+        }
 
         let mut z = Complex64::zero();
         let mut i = 0u64;
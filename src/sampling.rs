@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod multi_jittered;
 pub mod simple;
 pub mod stratified;
 
@@ -48,6 +49,28 @@ impl Sample {
     }
 }
 
+/// Returns a well-mixed hash of a pixel's coordinates. Samplers derive their jitter from it rather
+/// than from a random number generator, so that a pixel always gets the same samples, whichever
+/// tile or thread it is rendered in.
+pub fn pixel_seed(pixel_x: u32, pixel_y: u32) -> u32 {
+    hash(pixel_x ^ hash(pixel_y))
+}
+
+/// Returns a pseudo-random value in `[0, 1)` derived from `seed` and `index`.
+pub fn seeded_unit(seed: u32, index: u32) -> f64 {
+    hash(seed ^ hash(index)) as f64 / 4294967296.0
+}
+
+/// Integer hash with good avalanche behavior (the "lowbias32" hash by Chris Wellons).
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
 // ===== Sampler ===============================================================================================================================================
 
 impl<T: Iterator<Item=Sample>> Sampler for T {}
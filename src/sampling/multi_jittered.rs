@@ -0,0 +1,140 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::iter::FusedIterator;
+
+use crate::sampling::{Sample, pixel_seed};
+
+/// Correlated multi-jittered sampler, after Kensler, "Correlated Multi-Jittered Sampling" (Pixar
+/// Technical Memo 13-01, 2013).
+///
+/// The samples are stratified in 2D on an `m` by `n` grid and, at the same time, in 1D along each
+/// axis: projected onto the x or the y axis, every one of the `N` samples falls in its own stratum
+/// of width `1/N`. Good 1D projections matter for separable reconstruction filters, which weight
+/// the x and y offsets independently. Unlike the stratified sampler, any sample count is allowed.
+pub struct MultiJitteredSampler {
+    pixel_x: u32,
+    pixel_y: u32,
+    samples_per_pixel: u32,
+    grid_x: u32,
+    grid_y: u32,
+    pattern: u32,
+    index: u32,
+}
+
+// ===== MultiJitteredSampler ==================================================================================================================================
+
+impl MultiJitteredSampler {
+    /// Creates a correlated multi-jittered sampler that places `samples_per_pixel` samples within
+    /// the pixel. Each pixel gets its own pattern, derived from its coordinates, so neighboring
+    /// pixels are decorrelated and the same pixel always gets the same samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples_per_pixel` is zero.
+    pub fn new(pixel_x: u32, pixel_y: u32, samples_per_pixel: u32) -> MultiJitteredSampler {
+        assert!(samples_per_pixel > 0, "samples_per_pixel must be positive");
+
+        let grid_x = (samples_per_pixel as f64).sqrt() as u32;
+        let grid_y = samples_per_pixel.div_ceil(grid_x);
+
+        MultiJitteredSampler { pixel_x, pixel_y, samples_per_pixel, grid_x, grid_y, pattern: pixel_seed(pixel_x, pixel_y), index: 0 }
+    }
+}
+
+impl Iterator for MultiJitteredSampler {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index < self.samples_per_pixel {
+            let (n, m, p) = (self.samples_per_pixel, self.grid_x, self.pattern);
+
+            let s = permute(self.index, n, p.wrapping_mul(0x51633e2d));
+            let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+            let sy = permute(s / m, self.grid_y, p.wrapping_mul(0x02e5be93));
+            let jitter_x = random_unit(s, p.wrapping_mul(0x967a889b));
+            let jitter_y = random_unit(s, p.wrapping_mul(0x368cc8b7));
+
+            let offset_x = (sx as f64 + (sy as f64 + jitter_x) / self.grid_y as f64) / m as f64;
+            let offset_y = (s as f64 + jitter_y) / n as f64;
+
+            self.index += 1;
+            Some(Sample::new(self.pixel_x, self.pixel_y, offset_x, offset_y))
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.samples_per_pixel - self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for MultiJitteredSampler {}
+
+impl FusedIterator for MultiJitteredSampler {}
+
+/// Returns element `i` of a pseudo-random permutation of `0..len`, selected by the pattern `p`.
+/// This is Kensler's hash-based permutation, which works for any length without a lookup table.
+fn permute(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+
+    i.wrapping_add(p) % len
+}
+
+/// Returns a pseudo-random value in `[0, 1)` derived from `i` and the pattern `p`.
+fn random_unit(mut i: u32, p: u32) -> f64 {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb36534e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc4795);
+    i ^= 0xdf6e307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+
+    i as f64 / 4294967808.0
+}
@@ -14,9 +14,7 @@
 
 use std::iter::FusedIterator;
 
-use rand::random;
-
-use crate::sampling::Sample;
+use crate::sampling::{Sample, pixel_seed, seeded_unit};
 
 pub struct StratifiedSampler {
     pixel_x: u32,
@@ -24,6 +22,7 @@ pub struct StratifiedSampler {
     samples_per_pixel_x: u32,
     samples_per_pixel_y: u32,
     jitter: bool,
+    seed: u32,
     index_x: u32,
     index_y: u32,
 }
@@ -31,21 +30,20 @@ pub struct StratifiedSampler {
 // ===== StratifiedSampler =====================================================================================================================================
 
 impl StratifiedSampler {
-    /// Creates a stratified sampler that lays out `samples_per_pixel` samples on a square grid
-    /// within the pixel, optionally jittered.
+    /// Creates a stratified sampler that lays out a grid of `samples_per_pixel_x` by
+    /// `samples_per_pixel_y` samples within the pixel, optionally jittered.
     ///
-    /// `samples_per_pixel` must be a perfect square (1, 4, 9, 16, ...); the grid is then
-    /// `sqrt(samples_per_pixel)` cells on each side. A non-square count is deliberately not
-    /// supported: it would leave a partial row of cells, which needlessly complicates the sampler.
+    /// The grid does not have to be square; a rectangular grid is useful when a sample count is
+    /// not a perfect square (for example 8 samples as 4x2). The jitter is derived from the pixel's
+    /// coordinates, so the same pixel always gets the same samples.
     ///
     /// # Panics
     ///
-    /// Panics if `samples_per_pixel` is not a perfect square.
-    pub fn new(pixel_x: u32, pixel_y: u32, samples_per_pixel: u32, jitter: bool) -> StratifiedSampler {
-        let side = f32::sqrt(samples_per_pixel as f32).round() as u32;
-        assert_eq!(side * side, samples_per_pixel, "samples_per_pixel must be a perfect square, got {samples_per_pixel}");
+    /// Panics if either count is zero.
+    pub fn new(pixel_x: u32, pixel_y: u32, samples_per_pixel_x: u32, samples_per_pixel_y: u32, jitter: bool) -> StratifiedSampler {
+        assert!(samples_per_pixel_x > 0 && samples_per_pixel_y > 0, "samples per pixel must be positive, got {samples_per_pixel_x}x{samples_per_pixel_y}");
 
-        StratifiedSampler { pixel_x, pixel_y, samples_per_pixel_x: side, samples_per_pixel_y: side, jitter, index_x: 0, index_y: 0 }
+        StratifiedSampler { pixel_x, pixel_y, samples_per_pixel_x, samples_per_pixel_y, jitter, seed: pixel_seed(pixel_x, pixel_y), index_x: 0, index_y: 0 }
     }
 }
 
@@ -54,7 +52,8 @@ impl Iterator for StratifiedSampler {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.index_y < self.samples_per_pixel_y {
-            let (jitter_x, jitter_y) = if self.jitter { random() } else { (0.5, 0.5) };
+            let index = 2 * (self.index_y * self.samples_per_pixel_x + self.index_x);
+            let (jitter_x, jitter_y) = if self.jitter { (seeded_unit(self.seed, index), seeded_unit(self.seed, index + 1)) } else { (0.5, 0.5) };
             let offset_x = (self.index_x as f64 + jitter_x) / self.samples_per_pixel_x as f64;
             let offset_y = (self.index_y as f64 + jitter_y) / self.samples_per_pixel_y as f64;
             let sample = Sample::new(self.pixel_x, self.pixel_y, offset_x, offset_y);
@@ -0,0 +1,97 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transfer functions reshape the reconstructed values before they are mapped to colors, so that the
+//! palette is spread over the values that actually occur rather than evenly over `0.0..1.0`.
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The transfer function selected on the command line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferFunction {
+    /// Values are passed on unchanged.
+    #[default]
+    Linear,
+    /// Logarithm of the iteration count: brings out detail in the many low values.
+    Log,
+    /// Square root: a milder version of log.
+    Sqrt,
+    /// Raise values to the power --transfer-gamma.
+    Power,
+    /// Histogram equalization: every color of the palette covers about the same number of pixels.
+    Histogram,
+}
+
+/// A transfer function, ready to apply to values normalized by the maximum number of iterations.
+#[derive(Clone, Copy, Debug)]
+pub enum Transfer {
+    Linear,
+    Log { max_iterations: f64 },
+    Power { exponent: f64 },
+    Histogram,
+}
+
+// ===== Transfer ==============================================================================================================================================
+
+impl Transfer {
+    /// Creates a transfer. `gamma` is the exponent of the power function, and `max_iterations` is the
+    /// maximum number of iterations that the values are normalized by.
+    pub fn new(function: TransferFunction, gamma: f64, max_iterations: u64) -> Transfer {
+        match function {
+            TransferFunction::Linear => Transfer::Linear,
+            TransferFunction::Log => Transfer::Log { max_iterations: max_iterations as f64 },
+            TransferFunction::Sqrt => Transfer::Power { exponent: 0.5 },
+            TransferFunction::Power => Transfer::Power { exponent: gamma },
+            TransferFunction::Histogram => Transfer::Histogram,
+        }
+    }
+
+    /// Returns whether each value can be transferred on its own, without knowing the other values.
+    pub fn is_pointwise(&self) -> bool {
+        !matches!(self, Transfer::Histogram)
+    }
+
+    /// Transfers a single value. All functions map `0.0..=1.0` onto itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics for histogram equalization, which needs all values; see [`Transfer::apply_all`].
+    pub fn apply(&self, value: f64) -> f64 {
+        match *self {
+            Transfer::Linear => value,
+            Transfer::Log { max_iterations } => (value.max(0.0) * max_iterations).ln_1p() / max_iterations.ln_1p(),
+            Transfer::Power { exponent } => value.max(0.0).powf(exponent),
+            Transfer::Histogram => panic!("histogram equalization cannot be applied to a single value"),
+        }
+    }
+
+    /// Transfers the values of an image. Pixels without a value stay without one.
+    ///
+    /// Histogram equalization maps each value to the fraction of pixels with a value that is less than
+    /// or equal to it, so that the transferred values are spread evenly over `0.0..=1.0`.
+    pub fn apply_all(&self, values: &[Option<f64>]) -> Vec<Option<f64>> {
+        match self {
+            Transfer::Histogram => {
+                let mut sorted: Vec<f64> = values.iter().flatten().copied().collect();
+                sorted.par_sort_unstable_by(f64::total_cmp);
+                let count = sorted.len() as f64;
+                values.par_iter().map(|value| value.map(|value| sorted.partition_point(|&other| other <= value) as f64 / count)).collect()
+            }
+            _ => values.par_iter().map(|value| value.map(|value| self.apply(value))).collect(),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2024 Jesper de Jong
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Value files hold the reconstructed renderer output of an image, before it is mapped to colors,
+//! so that it can be post-processed in other tools or colored again without rendering.
+//!
+//! The format is simple: a fixed-size header followed by the values, all little-endian.
+//!
+//! | Offset | Type      | Field                                           |
+//! |--------|-----------|-------------------------------------------------|
+//! | 0      | `[u8; 8]` | magic bytes `MBVALUES`                          |
+//! | 8      | `u32`     | format version, currently 3                     |
+//! | 12     | `u32`     | width in pixels                                 |
+//! | 16     | `u32`     | height in pixels                                |
+//! | 20     | `u32`     | channels per pixel                              |
+//! | 24     | `f64`     | real part of the center of the view             |
+//! | 32     | `f64`     | imaginary part of the center of the view        |
+//! | 40     | `f64`     | scale of the view                               |
+//! | 48     | `u64`     | maximum number of iterations                    |
+//! | 56     | `u64`     | fingerprint of the render settings, 0 if none   |
+//! | 64     | `f64`     | rotation of the view, in degrees                |
+//! | 72     | `f64`...  | `width * height * channels` values              |
+//!
+//! Values are stored row by row, top row first, with the channels of a pixel next to each other.
+//! The first channel is the value; images with an alpha channel have a second channel with the
+//! coverage of the pixel, the fraction of its samples outside the set. Pixels without a value
+//! (inside the set) are NaN. With NumPy, a file can be loaded with:
+//!
+//! ```python
+//! values = numpy.fromfile(path, dtype="<f8", offset=72).reshape(height, width, channels)
+//! ```
+//!
+//! The fingerprint identifies all settings that affect the values (see [`fingerprint`]), so that a
+//! value file can serve as a cache: it is only reused when it was rendered with the same settings.
+//! Older files can still be read: version 2 files have no rotation and a 64-byte header, and version
+//! 1 files have no fingerprint either and a 56-byte header. Their views are not rotated.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"MBVALUES";
+const VERSION: u32 = 3;
+const HEADER_SIZE: usize = 72;
+
+/// The view an image was rendered with.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub center_re: f64,
+    pub center_im: f64,
+    pub scale: f64,
+    pub max_iterations: u64,
+    /// Counterclockwise rotation around the center, in degrees.
+    pub rotation: f64,
+}
+
+pub struct ValueFile {
+    width: u32,
+    height: u32,
+    channels: u32,
+    view: View,
+    fingerprint: u64,
+    values: Vec<f64>,
+}
+
+// ===== ValueFile =============================================================================================================================================
+
+impl ValueFile {
+    /// Creates a value file from `width * height * channels` values, in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values does not match the dimensions.
+    pub fn new(width: u32, height: u32, channels: u32, view: View, fingerprint: u64, values: Vec<f64>) -> ValueFile {
+        assert_eq!(values.len(), width as usize * height as usize * channels as usize, "number of values does not match the dimensions");
+
+        ValueFile { width, height, channels, view, fingerprint, values }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let error = |e| format!("cannot write value file {}: {e}", path.display());
+        let mut out = BufWriter::new(File::create(path).map_err(error)?);
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend(MAGIC);
+        header.extend(VERSION.to_le_bytes());
+        header.extend(self.width.to_le_bytes());
+        header.extend(self.height.to_le_bytes());
+        header.extend(self.channels.to_le_bytes());
+        header.extend(self.view.center_re.to_le_bytes());
+        header.extend(self.view.center_im.to_le_bytes());
+        header.extend(self.view.scale.to_le_bytes());
+        header.extend(self.view.max_iterations.to_le_bytes());
+        header.extend(self.fingerprint.to_le_bytes());
+        header.extend(self.view.rotation.to_le_bytes());
+        out.write_all(&header).map_err(error)?;
+
+        for value in &self.values {
+            out.write_all(&value.to_le_bytes()).map_err(error)?;
+        }
+        out.flush().map_err(error)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<ValueFile, Box<dyn Error>> {
+        let error = |e| format!("cannot read value file {}: {e}", path.display());
+        let mut input = BufReader::new(File::open(path).map_err(error)?);
+
+        let mut header = [0u8; HEADER_SIZE];
+        input.read_exact(&mut header[..56]).map_err(error)?;
+        if &header[0..8] != MAGIC {
+            return Err(format!("{} is not a value file", path.display()).into());
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let header_size = match version {
+            1 => 56,
+            2 => 64,
+            VERSION => HEADER_SIZE,
+            _ => return Err(format!("value file {} has unsupported version {version}", path.display()).into()),
+        };
+        input.read_exact(&mut header[56..header_size]).map_err(error)?;
+
+        let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        let u64_at = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+        let f64_at = |offset: usize| f64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+
+        let (width, height, channels) = (u32_at(12), u32_at(16), u32_at(20));
+        let rotation = if version >= 3 { f64_at(64) } else { 0.0 };
+        let view = View { center_re: f64_at(24), center_im: f64_at(32), scale: f64_at(40), max_iterations: u64_at(48), rotation };
+        let fingerprint = u64_at(56);
+
+        if channels == 0 {
+            return Err(format!("value file {} has no channels", path.display()).into());
+        }
+
+        // Check the size against the file before allocating, so that a corrupt header cannot cause a
+        // huge allocation.
+        let size = (width as usize).checked_mul(height as usize).and_then(|count| count.checked_mul(channels as usize)).and_then(|count| count.checked_mul(8));
+        let file_size = input.get_ref().metadata().map_err(error)?.len();
+        let Some(size) = size.filter(|&size| size as u64 == file_size.saturating_sub(header_size as u64)) else {
+            return Err(format!("value file {} is truncated or has trailing data", path.display()).into());
+        };
+        let mut bytes = Vec::with_capacity(size);
+        input.read_to_end(&mut bytes).map_err(error)?;
+        if bytes.len() != size {
+            return Err(format!("value file {} is truncated or has trailing data", path.display()).into());
+        }
+        let values = bytes.chunks_exact(8).map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap())).collect();
+
+        Ok(ValueFile { width, height, channels, view, fingerprint, values })
+    }
+}
+
+/// Computes the fingerprint of a description of render settings: the 64-bit FNV-1a hash of the
+/// text, which (unlike the standard library's hasher) is stable between builds. Zero is reserved
+/// for files without a fingerprint.
+pub fn fingerprint(settings: &str) -> u64 {
+    let hash = settings.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    hash.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a path for a temporary file, unique to the test.
+    fn temporary_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("mandelbrot-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn values_survive_a_round_trip() {
+        let view = View { center_re: -0.75, center_im: 0.1, scale: 2.5, max_iterations: 1000, rotation: 30.0 };
+        let values = vec![0.25, 1.0, f64::NAN, 0.0, 0.5, 1.0, f64::NAN, 0.0, 1e-300, 0.75, 0.125, 1.0];
+        let path = temporary_path("round-trip.mbv");
+        ValueFile::new(3, 2, 2, view, 42, values.clone()).write(&path).unwrap();
+        let file = ValueFile::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let file = file.unwrap();
+        assert_eq!((file.width(), file.height(), file.channels(), file.fingerprint()), (3, 2, 2, 42));
+        let read_view = file.view();
+        assert_eq!((read_view.center_re, read_view.center_im, read_view.scale, read_view.max_iterations, read_view.rotation), (-0.75, 0.1, 2.5, 1000, 30.0));
+        // NaN marks the interior, and must come back as NaN rather than compare equal.
+        for (read, written) in file.values().iter().zip(&values) {
+            assert!(read.to_bits() == written.to_bits(), "{read} != {written}");
+        }
+    }
+
+    #[test]
+    fn version_2_files_are_not_rotated() {
+        let view = View { center_re: 0.5, center_im: -0.25, scale: 1.5, max_iterations: 200, rotation: 45.0 };
+        let path = temporary_path("version-2.mbv");
+        ValueFile::new(1, 1, 1, view, 7, vec![0.5]).write(&path).unwrap();
+        // A version 2 file is a version 3 file without the rotation at the end of the header.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        bytes.drain(64..72);
+        std::fs::write(&path, bytes).unwrap();
+        let file = ValueFile::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let file = file.unwrap();
+        assert_eq!((file.view().scale, file.view().rotation, file.fingerprint(), file.values()), (1.5, 0.0, 7, &[0.5][..]));
+    }
+
+    #[test]
+    fn corrupt_headers_are_rejected() {
+        let view = View { center_re: 0.0, center_im: 0.0, scale: 1.0, max_iterations: 100, rotation: 0.0 };
+        let path = temporary_path("corrupt.mbv");
+        ValueFile::new(2, 2, 1, view, 1, vec![0.0; 4]).write(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        let mut results = Vec::new();
+        for (offset, field) in [(20, 0u32), (12, u32::MAX), (16, u32::MAX), (20, u32::MAX)] {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + 4].copy_from_slice(&field.to_le_bytes());
+            std::fs::write(&path, corrupt).unwrap();
+            results.push(ValueFile::read(&path).map(|_| ()).map_err(|e| e.to_string()));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(results[0].as_ref().unwrap_err().contains("no channels"), "{:?}", results[0]);
+        for result in &results[1..] {
+            assert!(result.as_ref().unwrap_err().contains("truncated"), "{result:?}");
+        }
+    }
+}